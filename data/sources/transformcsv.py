@@ -19,6 +19,9 @@ z0_idx = 0
 colour_index_idx = 0
 mag_src_idx = 0
 abs_mag_idx = 0
+vx_idx = 0
+vy_idx = 0
+vz_idx = 0
 
 header = None
 for line in file:
@@ -37,8 +40,11 @@ for line in file:
         colour_index_idx = header.index('ci')
         mag_src_idx = header.index('mag_src')
         abs_mag_idx = header.index('absmag')
+        vx_idx = header.index('vx')
+        vy_idx = header.index('vy')
+        vz_idx = header.index('vz')
         
-        writer.writerow(['name', 'x', 'y', 'z', 'colour_index', 'abs_mag'])
+        writer.writerow(['name', 'x', 'y', 'z', 'colour_index', 'abs_mag', 'vx', 'vy', 'vz'])
         continue
     
     name = ""
@@ -69,5 +75,10 @@ for line in file:
     colour_index = float(line[colour_index_idx]) * (0.85 if mag_src == "T" else 1.0) # convert BT-VT to B-V by multiplying by 0.85 (https://www.cosmos.esa.int/documents/532822/552851/vol1_all.pdf, section 1.3, equation 1.3.20, pg. 57)
 
     abs_mag = float(line[abs_mag_idx])
-    writer.writerow([name, x, y, z, colour_index, abs_mag])
+
+    # km/s, left empty for stars without a measured radial velocity
+    vx = line[vx_idx]
+    vy = line[vy_idx]
+    vz = line[vz_idx]
+    writer.writerow([name, x, y, z, colour_index, abs_mag, vx, vy, vz])
 
@@ -0,0 +1,45 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) colour: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(2) model0: vec4<f32>,
+    @location(3) model1: vec4<f32>,
+    @location(4) model2: vec4<f32>,
+    @location(5) model3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) colour: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> vp: mat4x4<f32>;
+
+@group(1) @binding(0)
+var<uniform> fovy_factor: f32;
+
+@group(2) @binding(0)
+var<uniform> model: mat4x4<f32>;
+
+@vertex
+fn vs_main(
+    vertex: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let world_pos = model * vec4<f32>(vertex.position, 1.0);
+
+    var out: VertexOutput;
+    out.colour = vertex.colour;
+    out.clip_position = vp * world_pos;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // surface brightness doesn't fall off with distance, the light of shader.wgsl's point is spread over every pixel the disc covers.
+    // pixels are pi / (1200 * fovy_factor) radians across
+    return vec4<f32>(in.colour / fovy_factor, 1.0);
+}
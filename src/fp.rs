@@ -1,10 +1,12 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Rem, RemAssign, Sub, SubAssign};
 
 use fixed::traits::ToFixed;
 
 pub type FP128 = fixed::types::I96F32;
 
-/// fixed point 128-bit vector
+/// fixed point 128-bit vector.
+/// like `FP128`, the arithmetic operators panic on overflow in debug builds and wrap in release builds,
+/// the `checked_` and `saturating_` methods report or clamp overflow instead
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Vec3F {
     pub x: FP128,
@@ -112,18 +114,183 @@ impl Vec3F {
         }
     }
 
+    /// squares are only taken of components scaled down to fit, so this only overflows if the length itself does
     pub fn length(&self) -> FP128 {
-        self.length_squared().sqrt()
+        let shift = self.safe_shift();
+        // magnitudes are shifted rather than the components, so the length doesn't depend on their signs
+        let scaled = Vec3F::new(
+            FP128::from_num(self.x.unsigned_abs() >> shift),
+            FP128::from_num(self.y.unsigned_abs() >> shift),
+            FP128::from_num(self.z.unsigned_abs() >> shift),
+        );
+        scaled.length_squared().sqrt() * (FP128::ONE << shift)
     }
 
+    /// overflows once components reach roughly 2^47, use `length` or `distance` for anything further apart
     pub fn length_squared(&self) -> FP128 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn max(&self) -> FP128 {
+    /// overflow safe like `length`, as long as the difference of the points fits in a `Vec3F`, which is true for any two points in the universe
+    pub fn distance(&self, other: Vec3F) -> FP128 {
+        (*self - other).length()
+    }
+
+    /// unit vector in the same direction, with the precision of `FP128` (about 2^-32). panics if the vector is zero
+    pub fn normalize(&self) -> Vec3F {
+        let scaled = self.shifted_right(self.safe_shift());
+        scaled / scaled.length()
+    }
+
+    /// right shift that brings every component below 2^46, so the squares of all three sum to less than 2^95
+    fn safe_shift(&self) -> u32 {
+        const SAFE_BITS: u32 = 46;
+
+        let max = self.x.unsigned_abs().max(self.y.unsigned_abs()).max(self.z.unsigned_abs());
+        FP128::INT_NBITS.saturating_sub(max.leading_zeros()).saturating_sub(SAFE_BITS)
+    }
+
+    /// divide by 2^shift, rounding towards negative infinity
+    fn shifted_right(&self, shift: u32) -> Vec3F {
+        Self {
+            x: self.x >> shift,
+            y: self.y >> shift,
+            z: self.z >> shift,
+        }
+    }
+
+    /// unit vector in the same direction, to the precision of `f64` rather than `FP128`. NaN if the vector is zero
+    pub fn direction(&self) -> glam::DVec3 {
+        self.shifted_right(self.safe_shift()).to_dvec3().normalize()
+    }
+
+    /// `self` at `t` = 0, `other` at `t` = 1
+    pub fn lerp(&self, other: Vec3F, t: FP128) -> Vec3F {
+        *self + (other - *self) * t
+    }
+
+    /// component-wise minimum
+    pub fn min(&self, other: Vec3F) -> Vec3F {
+        Self {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+            z: self.z.min(other.z),
+        }
+    }
+
+    /// component-wise maximum
+    pub fn max(&self, other: Vec3F) -> Vec3F {
+        Self {
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+            z: self.z.max(other.z),
+        }
+    }
+
+    /// component-wise clamp, panics if any component of `min` is greater than `max`
+    pub fn clamp(&self, min: Vec3F, max: Vec3F) -> Vec3F {
+        Self {
+            x: self.x.clamp(min.x, max.x),
+            y: self.y.clamp(min.y, max.y),
+            z: self.z.clamp(min.z, max.z),
+        }
+    }
+
+    pub fn min_element(&self) -> FP128 {
+        self.x.min(self.y.min(self.z))
+    }
+
+    pub fn max_element(&self) -> FP128 {
         self.x.max(self.y.max(self.z))
     }
 
+    /// component-wise floor to a whole number
+    pub fn floor(&self) -> Vec3F {
+        Self {
+            x: self.x.floor(),
+            y: self.y.floor(),
+            z: self.z.floor(),
+        }
+    }
+
+    /// component-wise remainder that is never negative, unlike `%`. panics if `rhs` is zero
+    pub fn rem_euclid(&self, rhs: FP128) -> Vec3F {
+        Self {
+            x: self.x.rem_euclid(rhs),
+            y: self.y.rem_euclid(rhs),
+            z: self.z.rem_euclid(rhs),
+        }
+    }
+
+    /// corner of the grid cell of size `grid` containing this point, i.e. each component rounded down to a multiple of `grid`
+    pub fn floor_to(&self, grid: FP128) -> Vec3F {
+        *self - self.rem_euclid(grid)
+    }
+
+    /// `None` if any component overflows
+    pub fn checked_add(&self, rhs: Vec3F) -> Option<Vec3F> {
+        Some(Self {
+            x: self.x.checked_add(rhs.x)?,
+            y: self.y.checked_add(rhs.y)?,
+            z: self.z.checked_add(rhs.z)?,
+        })
+    }
+
+    /// `None` if any component overflows
+    pub fn checked_sub(&self, rhs: Vec3F) -> Option<Vec3F> {
+        Some(Self {
+            x: self.x.checked_sub(rhs.x)?,
+            y: self.y.checked_sub(rhs.y)?,
+            z: self.z.checked_sub(rhs.z)?,
+        })
+    }
+
+    /// component-wise product, `None` if any component overflows
+    pub fn checked_mul(&self, rhs: Vec3F) -> Option<Vec3F> {
+        Some(Self {
+            x: self.x.checked_mul(rhs.x)?,
+            y: self.y.checked_mul(rhs.y)?,
+            z: self.z.checked_mul(rhs.z)?,
+        })
+    }
+
+    /// `None` if any component overflows
+    pub fn checked_mul_scalar(&self, rhs: FP128) -> Option<Vec3F> {
+        self.checked_mul(Vec3F::splat(rhs))
+    }
+
+    /// components that overflow are clamped to `FP128::MIN` or `FP128::MAX`
+    pub fn saturating_add(&self, rhs: Vec3F) -> Vec3F {
+        Self {
+            x: self.x.saturating_add(rhs.x),
+            y: self.y.saturating_add(rhs.y),
+            z: self.z.saturating_add(rhs.z),
+        }
+    }
+
+    /// components that overflow are clamped to `FP128::MIN` or `FP128::MAX`
+    pub fn saturating_sub(&self, rhs: Vec3F) -> Vec3F {
+        Self {
+            x: self.x.saturating_sub(rhs.x),
+            y: self.y.saturating_sub(rhs.y),
+            z: self.z.saturating_sub(rhs.z),
+        }
+    }
+
+    /// component-wise product, components that overflow are clamped to `FP128::MIN` or `FP128::MAX`
+    pub fn saturating_mul(&self, rhs: Vec3F) -> Vec3F {
+        Self {
+            x: self.x.saturating_mul(rhs.x),
+            y: self.y.saturating_mul(rhs.y),
+            z: self.z.saturating_mul(rhs.z),
+        }
+    }
+
+    /// components that overflow are clamped to `FP128::MIN` or `FP128::MAX`
+    pub fn saturating_mul_scalar(&self, rhs: FP128) -> Vec3F {
+        self.saturating_mul(Vec3F::splat(rhs))
+    }
+
     pub fn abs(&self) -> Vec3F {
         Self {
             x: self.x.abs(),
@@ -409,6 +576,48 @@ impl DivAssign<f64> for Vec3F {
     }
 }
 
+impl Rem<FP128> for Vec3F {
+    type Output = Self;
+
+    /// component-wise remainder, with the sign of `self` like `FP128`
+    fn rem(self, rhs: FP128) -> Self::Output {
+        Self {
+            x: self.x % rhs,
+            y: self.y % rhs,
+            z: self.z % rhs,
+        }
+    }
+}
+
+impl Rem for Vec3F {
+    type Output = Self;
+
+    fn rem(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x % rhs.x,
+            y: self.y % rhs.y,
+            z: self.z % rhs.z,
+        }
+    }
+}
+
+impl RemAssign<FP128> for Vec3F {
+    fn rem_assign(&mut self, rhs: FP128) {
+        self.x %= rhs;
+        self.y %= rhs;
+        self.z %= rhs;
+    }
+}
+
+impl RemAssign for Vec3F {
+    fn rem_assign(&mut self, rhs: Self) {
+        self.x %= rhs.x;
+        self.y %= rhs.y;
+        self.z %= rhs.z;
+    }
+}
+
+/// rotate a fixed point vector. `Quat` is rounded to `FP128`, which loses precision for large vectors, `DQuat` rotates in f64
 pub trait MulVec3F {
     fn mul_vec3f(&self, other: Vec3F) -> Vec3F;
 }
@@ -420,6 +629,15 @@ impl MulVec3F for glam::Quat {
     }
 }
 
+impl MulVec3F for glam::DQuat {
+    fn mul_vec3f(&self, other: Vec3F) -> Vec3F {
+        // the bits that don't fit in an f64 are rotated separately, so e.g. the identity keeps the vector exactly
+        let high = other.to_dvec3();
+        let low = (other - Vec3F::from_dvec3(high)).to_dvec3();
+        Vec3F::from_dvec3(*self * high) + Vec3F::from_dvec3(*self * low)
+    }
+}
+
 impl Neg for Vec3F {
     type Output = Self;
 
@@ -438,6 +656,117 @@ impl Default for Vec3F {
     }
 }
 
+/// axis aligned box with inclusive min and exclusive max, so neighbouring boxes and the octants of a box never share a point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Aabb {
+    pub min: Vec3F,
+    pub max: Vec3F,
+}
+
+impl Aabb {
+    /// panics if any component of `min` is greater than `max`
+    pub fn new(min: Vec3F, max: Vec3F) -> Self {
+        assert!(min.x <= max.x && min.y <= max.y && min.z <= max.z, "invalid bounds {min:?} {max:?}");
+        Self { min, max }
+    }
+
+    /// the box of size `size` with the given centre
+    pub fn from_centre_size(centre: Vec3F, size: Vec3F) -> Self {
+        let min = centre - size / FP128::from_num(2);
+        Self::new(min, min + size)
+    }
+
+    pub fn size(&self) -> Vec3F {
+        self.max - self.min
+    }
+
+    /// where the box is split into octants, rounded towards `min`
+    pub fn centre(&self) -> Vec3F {
+        self.min + self.size() / FP128::from_num(2)
+    }
+
+    /// distance from the centre to the furthest corner
+    pub fn radius(&self) -> FP128 {
+        (self.max - self.centre()).length()
+    }
+
+    pub fn contains(&self, point: Vec3F) -> bool {
+        self.min.x <= point.x && self.min.y <= point.y && self.min.z <= point.z &&
+        point.x < self.max.x && point.y < self.max.y && point.z < self.max.z
+    }
+
+    /// whether the box contains no points, i.e. has zero size along some axis
+    pub fn is_empty(&self) -> bool {
+        self.min.x == self.max.x || self.min.y == self.max.y || self.min.z == self.max.z
+    }
+
+    /// whether the boxes share any point, empty boxes intersect nothing
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        !self.is_empty() && !other.is_empty() &&
+        self.min.x < other.max.x && self.min.y < other.max.y && self.min.z < other.max.z &&
+        other.min.x < self.max.x && other.min.y < self.max.y && other.min.z < self.max.z
+    }
+
+    /// point on or in the box closest to `point`, `point` itself if it is inside. the exclusive max is treated as part of the box
+    pub fn closest_point(&self, point: Vec3F) -> Vec3F {
+        point.clamp(self.min, self.max)
+    }
+
+    /// distance from a point to the closest point of the box, zero if inside
+    pub fn distance_to(&self, point: Vec3F) -> FP128 {
+        self.closest_point(point).distance(point)
+    }
+
+    /// whether any part of the box is within `radius` of `centre` (inclusive)
+    pub fn intersects_sphere(&self, centre: Vec3F, radius: FP128) -> bool {
+        self.distance_to(centre) <= radius
+    }
+
+    /// whether any part of the box's bounding sphere is within `tolerance` radians of the ray from `origin`, `direction` must be normalised
+    pub fn in_cone(&self, origin: Vec3F, direction: glam::DVec3, tolerance: f64) -> bool {
+        let to_centre = (self.centre() - origin).to_dvec3();
+        let dist = to_centre.length();
+        let radius = self.radius().to_num::<f64>();
+
+        // inside bounding sphere
+        if dist <= radius {
+            return true;
+        }
+
+        let angle = to_centre.angle_between(direction);
+        angle - (radius / dist).asin() <= tolerance
+    }
+
+    /// index of the octant containing `point`, see `Aabb::octant`. `None` if the point is outside the box
+    pub fn octant_index(&self, point: Vec3F) -> Option<usize> {
+        if !self.contains(point) {
+            return None;
+        }
+
+        let centre = self.centre();
+        Some(((point.x >= centre.x) as usize) << 2 | ((point.y >= centre.y) as usize) << 1 | (point.z >= centre.z) as usize)
+    }
+
+    /// one eighth of the box, bits 2, 1 and 0 of `index` select the upper half along x, y and z, the same order as `tree::Octant`
+    pub fn octant(&self, index: usize) -> Aabb {
+        assert!(index < 8, "octant index {index} out of range");
+
+        let centre = self.centre();
+        let (mut min, mut max) = (self.min, centre);
+        if index & 0b100 != 0 {
+            (min.x, max.x) = (centre.x, self.max.x);
+        }
+        if index & 0b010 != 0 {
+            (min.y, max.y) = (centre.y, self.max.y);
+        }
+        if index & 0b001 != 0 {
+            (min.z, max.z) = (centre.z, self.max.z);
+        }
+
+        Self { min, max }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -558,11 +887,195 @@ mod tests {
         v /= 2.0f64;
         assert_eq!(x123, v);
 
+        // rem
+        let x357 = Vec3F::new(3*f1, 5*f1, 7*f1);
+        assert_eq!(Vec3F::new(f1, f1, f1), x357 % (2*f1));
+        assert_eq!(Vec3F::new(f1, f1, f1), x357 % x246);
+        assert_eq!(Vec3F::new(-f1, -f1, -f1), -x357 % (2*f1));
+
+        // rem assign
+        let mut v = x357;
+        v %= 2*f1;
+        assert_eq!(Vec3F::ONE, v);
+
+        let mut v = x357;
+        v %= x246;
+        assert_eq!(Vec3F::ONE, v);
+
         // quat mul
         assert!((glam::Quat::from_axis_angle(glam::Vec3::Y, std::f32::consts::FRAC_PI_2).mul_vec3f(Vec3F::X) - Vec3F::N_Z).length() < 0.00001);
+        assert!((glam::DQuat::from_axis_angle(glam::DVec3::Y, std::f64::consts::FRAC_PI_2).mul_vec3f(Vec3F::X) - Vec3F::N_Z).length() < 0.00001);
+        let far = Vec3F::new(fixed!(1237940039285380274899124224: I96F32) + FP128::DELTA, -f1, FP128::DELTA); // 2^90m
+        assert_eq!(far, glam::DQuat::IDENTITY.mul_vec3f(far));
+        let rotated = glam::DQuat::from_axis_angle(glam::DVec3::Z, std::f64::consts::PI).mul_vec3f(far);
+        assert!((rotated + far).abs().max_element() < 1.0e12, "{rotated:?}");
 
         // neg
         let n123 = Vec3F::new(-f1, -2*f1, -3*f1);
         assert_eq!(n123, -x123);
     }
+
+    #[test]
+    fn components() {
+        let f1 = FP128::ONE;
+        let a = Vec3F::new(f1, -2*f1, 3*f1);
+        let b = Vec3F::new(-f1, 4*f1, 2*f1);
+
+        // min, max
+        assert_eq!(Vec3F::new(-f1, -2*f1, 2*f1), a.min(b));
+        assert_eq!(Vec3F::new(f1, 4*f1, 3*f1), a.max(b));
+        assert_eq!(-2*f1, a.min_element());
+        assert_eq!(3*f1, a.max_element());
+
+        // clamp
+        assert_eq!(Vec3F::new(f1, -f1, f1), a.clamp(Vec3F::N_ONE, Vec3F::ONE));
+        assert_eq!(a, a.clamp(a, a));
+
+        // lerp
+        assert_eq!(a, a.lerp(b, FP128::ZERO));
+        assert_eq!(b, a.lerp(b, f1));
+        assert_eq!(Vec3F::new(FP128::ZERO, f1, fixed!(2.5: I96F32)), a.lerp(b, fixed!(0.5: I96F32)));
+
+        // direction
+        assert!((Vec3F::new(3*f1, FP128::ZERO, -4*f1).direction() - glam::dvec3(0.6, 0.0, -0.8)).abs().max_element() < 1e-15);
+        let far = Vec3F::new(fixed!(1237940039285380274899124224: I96F32), FP128::DELTA, -f1); // 2^90m
+        assert!((far.direction() - glam::DVec3::X).abs().max_element() < 1e-15);
+        assert!(Vec3F::ZERO.direction().is_nan());
+
+        // floor, grid
+        let p = Vec3F::from_f64s(2.5, -2.5, 7.0);
+        assert_eq!(Vec3F::new(2*f1, -3*f1, 7*f1), p.floor());
+        assert_eq!(Vec3F::from_f64s(0.5, 1.5, 1.0), p.rem_euclid(2*f1));
+        assert_eq!(Vec3F::new(2*f1, -4*f1, 6*f1), p.floor_to(2*f1));
+        assert_eq!(p, p.floor_to(FP128::DELTA));
+    }
+
+    #[test]
+    fn overflow() {
+        let f1 = FP128::ONE;
+        let big = Vec3F::new(FP128::MAX, f1, FP128::MIN);
+
+        // checked
+        assert_eq!(Some(Vec3F::new(FP128::MAX, 2*f1, FP128::MIN + f1)), big.checked_add(Vec3F::new(FP128::ZERO, f1, f1)));
+        assert_eq!(None, big.checked_add(Vec3F::X * FP128::DELTA));
+        assert_eq!(None, big.checked_sub(Vec3F::Z * FP128::DELTA));
+        assert_eq!(Some(Vec3F::ZERO), big.checked_sub(big));
+        assert_eq!(None, big.checked_mul(Vec3F::ONE * (2*f1)));
+        assert_eq!(Some(Vec3F::new(FP128::MAX, -f1, FP128::MIN)), big.checked_mul(Vec3F::new(f1, -f1, f1)));
+        assert_eq!(None, big.checked_mul_scalar(-f1));
+        assert_eq!(Some(big), big.checked_mul_scalar(f1));
+
+        // saturating
+        assert_eq!(Vec3F::new(FP128::MAX, 2*f1, FP128::MIN + f1), big.saturating_add(Vec3F::ONE));
+        assert_eq!(Vec3F::new(FP128::MAX - f1, FP128::ZERO, FP128::MIN), big.saturating_sub(Vec3F::ONE));
+        assert_eq!(Vec3F::new(FP128::MIN, 3*f1, FP128::MAX), big.saturating_mul(Vec3F::new(-2*f1, 3*f1, -2*f1)));
+        assert_eq!(Vec3F::new(FP128::MAX, 4*f1, FP128::MIN), big.saturating_mul_scalar(4*f1));
+
+        // operators panic in debug builds and wrap in release builds
+        let add = std::panic::catch_unwind(|| big + Vec3F::ONE);
+        if cfg!(debug_assertions) {
+            assert!(add.is_err());
+        } else {
+            assert_eq!(FP128::MIN, add.unwrap().x);
+        }
+    }
+
+    #[test]
+    fn aabb() {
+        let f1 = FP128::ONE;
+        let aabb = Aabb::new(Vec3F::ZERO, Vec3F::new(4*f1, 2*f1, 8*f1));
+
+        assert_eq!(Vec3F::new(2*f1, f1, 4*f1), aabb.centre());
+        assert_eq!(Vec3F::new(4*f1, 2*f1, 8*f1), aabb.size());
+        assert_eq!(Aabb::from_centre_size(aabb.centre(), aabb.size()), aabb);
+        assert_eq!(aabb.radius(), Vec3F::new(2*f1, f1, 4*f1).length());
+
+        // inclusive min, exclusive max
+        assert!(aabb.contains(Vec3F::ZERO));
+        assert!(aabb.contains(aabb.max - Vec3F::splat(FP128::DELTA)));
+        assert!(!aabb.contains(aabb.max));
+        assert!(!aabb.contains(Vec3F::new(f1, 2*f1, f1)));
+        assert!(!aabb.contains(Vec3F::N_X * FP128::DELTA));
+
+        // touching boxes don't intersect
+        assert!(aabb.intersects(&aabb));
+        assert!(aabb.intersects(&Aabb::new(Vec3F::splat(f1), Vec3F::splat(10*f1))));
+        assert!(!aabb.intersects(&Aabb::new(Vec3F::new(4*f1, FP128::ZERO, FP128::ZERO), Vec3F::splat(10*f1))));
+        assert!(!aabb.intersects(&Aabb::new(Vec3F::ONE, Vec3F::ONE)));
+
+        // closest point, distance
+        assert_eq!(Vec3F::ONE, aabb.closest_point(Vec3F::ONE));
+        assert_eq!(Vec3F::new(4*f1, FP128::ZERO, 8*f1), aabb.closest_point(Vec3F::new(7*f1, -3*f1, 8*f1)));
+        assert_eq!(FP128::ZERO, aabb.distance_to(Vec3F::ONE));
+        assert_eq!(5*f1, aabb.distance_to(Vec3F::new(7*f1, -4*f1, 8*f1)));
+
+        // sphere
+        assert!(aabb.intersects_sphere(Vec3F::new(7*f1, -4*f1, 8*f1), 5*f1));
+        assert!(!aabb.intersects_sphere(Vec3F::new(7*f1, -4*f1, 8*f1), 5*f1 - FP128::DELTA));
+        assert!(aabb.intersects_sphere(Vec3F::ONE, FP128::ZERO));
+
+        // cone
+        assert!(aabb.in_cone(Vec3F::ONE, glam::DVec3::NEG_X, 0.0));
+        assert!(aabb.in_cone(Vec3F::new(-10*f1, FP128::ZERO, FP128::ZERO), glam::DVec3::X, 0.0));
+        assert!(!aabb.in_cone(Vec3F::new(-10*f1, FP128::ZERO, FP128::ZERO), glam::DVec3::NEG_X, 0.1));
+        assert!(aabb.in_cone(Vec3F::new(-10*f1, FP128::ZERO, FP128::ZERO), glam::DVec3::NEG_X, std::f64::consts::PI));
+
+        // octants tile the box, each point is in exactly the octant it reports
+        let octants = (0..8).map(|i| aabb.octant(i)).collect::<Vec<_>>();
+        assert_eq!(Aabb::new(Vec3F::ZERO, aabb.centre()), octants[0]);
+        assert_eq!(Aabb::new(aabb.centre(), aabb.max), octants[7]);
+        assert_eq!(Aabb::new(Vec3F::new(2*f1, FP128::ZERO, 4*f1), Vec3F::new(4*f1, f1, 8*f1)), octants[0b101]);
+        for x in 0..8 {
+            for y in 0..4 {
+                for z in 0..16 {
+                    let point = Vec3F::new(x * f1 / 2, y * f1 / 2, z * f1 / 2);
+                    let index = aabb.octant_index(point).unwrap();
+                    assert_eq!(octants.iter().filter(|o| o.contains(point)).count(), 1);
+                    assert!(octants[index].contains(point));
+                }
+            }
+        }
+        assert_eq!(None, aabb.octant_index(aabb.max));
+    }
+
+    #[test]
+    fn length() {
+        let f1 = FP128::ONE;
+
+        // exact for small vectors
+        assert_eq!(Vec3F::new(3*f1, 4*f1, FP128::ZERO).length(), 5*f1);
+        assert_eq!(Vec3F::new(-2*f1, 3*f1, 6*f1).length(), 7*f1);
+        assert_eq!(Vec3F::ZERO.length(), FP128::ZERO);
+        assert!((Vec3F::new(-3*f1, -4*f1, FP128::ZERO).normalize() - Vec3F::from_f64s(-0.6, -0.8, 0.0)).abs().max_element() <= FP128::DELTA);
+
+        let region = fixed!(4951760157141521099596496896: I96F32); // 2^92m, `Universe::REGION_SIZE`
+        let corner = Vec3F::splat(region / 2);
+
+        // far beyond where `length_squared` overflows, compared against f64
+        let cases = [
+            (Vec3F::from_f64s(3.0e14, -4.0e14, 1.0e14), Vec3F::ZERO),
+            (corner, -corner),
+            (Vec3F::new(region / 2, -region / 2 + f1, FP128::ZERO), Vec3F::new(-region / 2, region / 2 - 7*f1, f1)),
+            (Vec3F::from_f64s(1.23e27, -4.56e26, 7.89e25), Vec3F::from_f64s(-9.87e26, 6.54e24, -3.21e27)),
+            // close together, far from the origin
+            (Vec3F::from_f64s(1.0e27, 1.0e-3, 0.0), Vec3F::from_f64s(1.0e27, 0.0, 0.0) - Vec3F::from_f64s(1.0e5, 0.0, 2.0)),
+        ];
+        for (a, b) in cases {
+            // the difference is exact in fixed point, but not in f64
+            let expected = (a - b).to_dvec3().length();
+
+            let distance = a.distance(b);
+            assert!((distance.to_num::<f64>() - expected).abs() <= expected * 1e-12, "{a:?} {b:?} {distance} {expected}");
+            assert_eq!(distance, b.distance(a));
+            assert_eq!(distance, (a - b).length());
+
+            let direction = (a - b).normalize();
+            let expected = (a - b).to_dvec3().normalize();
+            assert!((direction.to_dvec3() - expected).abs().max_element() < 1e-9, "{direction:?} {expected:?}");
+        }
+
+        // the longest distance in the universe still fits
+        let diagonal = corner.distance(-corner).to_num::<f64>();
+        assert!((diagonal / (3.0f64.sqrt() * 2.0f64.powi(92)) - 1.0).abs() < 1e-12);
+    }
 }
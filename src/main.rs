@@ -2,7 +2,7 @@ use std::{collections::HashMap, fs::File, io::Write, sync::{mpsc, Arc}, time::In
 
 use clap::Parser;
 use flate2::write::GzEncoder;
-use fp::Vec3F;
+use fp::{Vec3F, FP128};
 use wgpu::util::DeviceExt;
 use winit::{event::{ElementState, Event, KeyEvent, WindowEvent}, event_loop::EventLoop, keyboard::{KeyCode, PhysicalKey}, window::{Window, WindowBuilder}};
 
@@ -14,8 +14,10 @@ extern crate static_assertions;
 extern crate maplit;
 
 mod fp;
+mod spectrum;
 mod transform;
 mod tree;
+mod units;
 mod universe;
 mod render;
 
@@ -24,6 +26,8 @@ struct StarBuffer {
     model_uniform: render::UniformBuffer<glam::Mat4>,
     model_bind_group: wgpu::BindGroup,
     mesh: Arc<render::Mesh>,
+    /// drawn as a disc by `State::disc_pipeline`, rather than as points
+    resolved: bool,
 }
 
 struct State<'a> {
@@ -34,6 +38,7 @@ struct State<'a> {
     window: &'a Window,
     frame_count: usize,
     main_pipeline: render::Pipeline,
+    disc_pipeline: render::Pipeline,
     tonemap_pipeline: render::Pipeline,
     // postprocess_queue: render::PostprocessQueue,
     render_graph: Option<render::RenderGraph>,
@@ -45,13 +50,17 @@ struct State<'a> {
     fovy_factor: render::UniformBuffer<f32>,
     fovy_factor_bind_group: wgpu::BindGroup,
     vis_rx: mpsc::Receiver<Vec<StarBuffer>>,
-    vis_tx: Option<mpsc::Sender<(Vec3F, f32, u32)>>,
+    vis_tx: Option<mpsc::Sender<tree::View>>,
     vis_handle: Option<std::thread::JoinHandle<()>>,
     star_buffers: Vec<StarBuffer>,
+    min_flux: f64,
 }
 
 impl<'a> State<'a> {
-    async fn new(window: &'a Window, mut universe: universe::Universe) -> State<'a> {
+    /// how far the camera moves each frame
+    const CAMERA_STEP: units::Au<glam::DVec3> = units::Au(glam::DVec3::new(0.0, 0.0, -1031.4));
+
+    async fn new(window: &'a Window, mut universe: universe::Universe, min_flux: f64, camera_position: units::Metres<Vec3F>) -> State<'a> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -101,7 +110,7 @@ impl<'a> State<'a> {
 
         let depth = render::Texture::new_depth(&renderer, size.width, size.height);
 
-        let camera = render::Camera::new(transform::Transform::with_translation(Vec3F::from_f64s(1.543e+11, 0.0, 1.0e17)), std::f32::consts::FRAC_PI_2);
+        let camera = render::Camera::new(transform::Transform::with_translation(camera_position.into()), std::f32::consts::FRAC_PI_2);
         let camera_uniform = render::UniformBuffer::new(Arc::clone(&renderer), camera.perspective(1.0));
 
         let camera_layout = camera_uniform.bind_group_layout();
@@ -154,6 +163,7 @@ impl<'a> State<'a> {
         };
 
         let main_pipeline = render::Pipeline::new(Arc::clone(&renderer), &wgsl_preprocessor::preprocess!("shaders/shader.wgsl").0, wgpu::PrimitiveTopology::PointList, render::Texture::HDR_FORMAT, false, &[render::Vertex::LAYOUT, render::Instance::LAYOUT], &[&camera_layout, &rads_per_pixel_layout, &model_layout], render::BlendMode::Add).unwrap();
+        let disc_pipeline = render::Pipeline::new(Arc::clone(&renderer), &wgsl_preprocessor::preprocess!("shaders/disc.wgsl").0, wgpu::PrimitiveTopology::TriangleList, render::Texture::HDR_FORMAT, false, &[render::Vertex::LAYOUT, render::Instance::LAYOUT], &[&camera_layout, &rads_per_pixel_layout, &model_layout], render::BlendMode::Add).unwrap();
         let tonemap_pipeline = render::Pipeline::new(Arc::clone(&renderer), &wgsl_preprocessor::preprocess!("shaders/postprocess/tonemap.wgsl").0, wgpu::PrimitiveTopology::TriangleStrip, config.format, false, &[], &[&hdr_buffer.1], render::BlendMode::Normal).unwrap();
 
         let render_graph = {
@@ -244,89 +254,84 @@ impl<'a> State<'a> {
                 let mut star_cache = HashMap::new();
 
                 'outer: loop {
-                    let mut camera_pos = None;
-                    // skip extras camera_positions if present in buffer
+                    let mut view = None;
+                    // skip extras views if present in buffer
                     loop {
                         match rx.try_recv() {
-                            Ok(p) => camera_pos = Some(p),
+                            Ok(v) => view = Some(v),
                             Err(mpsc::TryRecvError::Disconnected) => break 'outer,
-                            Err(mpsc::TryRecvError::Empty) => if camera_pos.is_some() {
+                            Err(mpsc::TryRecvError::Empty) => if view.is_some() {
                                 break;
                             } else {
-                                let Ok((cam_pos, fovy, screen_height)) = rx.recv() else { break 'outer };
-                                camera_pos = Some((cam_pos, fovy, screen_height));
+                                let Ok(v) = rx.recv() else { break 'outer };
+                                view = Some(v);
                             },
                         }
                     }
                     
                     log::debug!("calculating visibility");
                     
-                    let (camera_pos, fovy, screen_height) = camera_pos.expect("unreachable");
+                    let view: tree::View = view.expect("unreachable");
+                    let camera_pos = view.position;
 
-                    let visible = universe.all_visible_from(camera_pos, fovy, screen_height);
-                    log::debug!("found {} visible regions", visible.len());
-        
-                    for (fresh, _, _) in star_cache.values_mut() {
-                        *fresh = false;
-                    }
+                    let delta = universe.all_visible_from(&view);
+                    log::debug!("visibility changed: {} added, {} changed, {} removed", delta.added.len(), delta.changed.len(), delta.removed.len());
+                    log::debug!("octree cells evicted: {:?}", universe.counters());
 
-                    let num_bodies: usize = visible.iter().map(|c| c.bodies.iter().map(|b| b.is_body as usize)).flatten().sum();
-                    let total: usize = visible.iter().map(|c| c.bodies.len()).sum();
+                    for id in &delta.removed {
+                        star_cache.remove(id);
+                    }
 
-                    log::debug!("{} visible regions contain {total} bodies", visible.len());
-                    log::debug!("generating {} meshes...", visible.len() );
+                    log::debug!("generating {} meshes...", delta.added.len() + delta.changed.len());
             
-                    for cell_v in visible {
-                        // if stars already cached, just update model matrix
-                        if let Some((fresh, _, _)) = star_cache.get_mut(&cell_v) {
-                            *fresh = true;
-                            continue;
-                        }
-
+                    for cell_v in delta.added.into_iter().chain(delta.changed) {
                         let pos = cell_v.centre;
             
-                        let vertices = cell_v.bodies.iter().map(|tree::PointLight { position, colour, .. }| {
+                        let (resolved, points): (Vec<_>, Vec<_>) = cell_v.bodies.iter().partition(|b| b.resolved);
+            
+                        let vertices = points.into_iter().map(|tree::PointLight { position, spectrum, .. }| {
                             render::Vertex {
                                 position: (*position - cell_v.centre).to_vec3(),
-                                colour: (*colour / 1.0e8).as_vec3(), // scale down to prevent overflow
+                                colour: (spectrum.to_rgb() / 1.0e8).as_vec3(), // scale down to prevent overflow
                             }
                         }).collect::<Vec<_>>();
 
-                        star_cache.insert(cell_v, (true, pos, Arc::new(render::Mesh::new(&renderer, &vertices))));
+                        // each disc gets its own mesh around the body, f32 can't place it precisely enough relative to the cell
+                        let discs = resolved.into_iter().map(|tree::PointLight { position, diameter, spectrum, .. }| {
+                            let radius = diameter.to_num::<f64>() / 2.0;
+                            // the point's brightness spread over the pixels the disc covers, fs_main in shaders/disc.wgsl divides by the fovy factor
+                            let surface = spectrum.to_rgb() * std::f64::consts::PI / (1200.0 * 1200.0 * radius * radius);
+                            (*position, Arc::new(render::Mesh::sphere(&renderer, radius as f32, surface.as_vec3())))
+                        }).collect::<Vec<_>>();
+
+                        let num_bodies = cell_v.bodies.iter().filter(|b| b.kind.is_some()).count();
+
+                        star_cache.insert(cell_v.id, (pos, Arc::new(render::Mesh::new(&renderer, &vertices)), discs, num_bodies, cell_v.bodies.len()));
                     }
                     
                     log::debug!("generated meshes");
-                    
-                    {
-                        let mut old = vec![];
-                        
-                        for (k, (fresh, _, _)) in &star_cache {
-                            if !fresh {
-                                old.push(k.clone());
-                            }
-                        }
-            
-                        log::debug!("clearing mesh cache of {} outdated meshes", old.len());
-                        
-                        for k in old {
-                            star_cache.remove(&k);
-                        }
-                    }
+
+                    let num_bodies: usize = star_cache.values().map(|(_, _, _, n, _)| n).sum();
+                    let total: usize = star_cache.values().map(|(_, _, _, _, n)| n).sum();
 
                     log::info!("calculated visibility: {num_bodies} bodies, {} point approx, {total} total", total - num_bodies);
 
                     let mut v = vec![];
 
-                    for (_, pos, mesh) in star_cache.values() {
-                        let model = render::UniformBuffer::new(Arc::clone(&renderer), glam::Mat4::from_translation((*pos - camera_pos).to_vec3()));
-                        let bind_group = model.bind_group(&model_layout);
-                        
-                        v.push(StarBuffer {
-                            centre: *pos,
-                            model_uniform: model,
-                            model_bind_group: bind_group,
-                            mesh: Arc::clone(&mesh),
-                        });
+                    for (pos, mesh, discs, _, _) in star_cache.values() {
+                        let meshes = std::iter::once((pos, mesh, false)).chain(discs.iter().map(|(pos, mesh)| (pos, mesh, true)));
+                        for (pos, mesh, resolved) in meshes {
+                            let model = render::UniformBuffer::new(Arc::clone(&renderer), glam::Mat4::from_translation((*pos - camera_pos).to_vec3()));
+                            let bind_group = model.bind_group(&model_layout);
+                            
+                            v.push(StarBuffer {
+                                centre: *pos,
+                                model_uniform: model,
+                                model_bind_group: bind_group,
+                                mesh: Arc::clone(&mesh),
+                                resolved,
+                            });
+                        }
                     }
 
                     v.sort_by_key(|b| -((b.centre - camera_pos).to_dvec3().length() / 1.0e9) as i128);
@@ -346,6 +351,7 @@ impl<'a> State<'a> {
             window,
             frame_count: 0,
             main_pipeline,
+            disc_pipeline,
             tonemap_pipeline,
             // postprocess_queue,
             render_graph: Some(render_graph),
@@ -360,6 +366,7 @@ impl<'a> State<'a> {
             vis_tx: Some(vis_tx),
             vis_handle: Some(vis_handle),
             star_buffers: vec![],
+            min_flux,
         }
     }
 
@@ -402,22 +409,23 @@ impl<'a> State<'a> {
     }
 
     fn update(&mut self) {
-        self.camera.transform.translation -= Vec3F::Z * 1.543e+14;
+        self.camera.transform.translation += Vec3F::from(Self::CAMERA_STEP);
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        self.camera_uniform.mutate(self.camera.perspective(self.size.width as f32 / self.size.height as f32));
+        let aspect = self.size.width as f32 / self.size.height as f32;
+        self.camera_uniform.mutate(self.camera.perspective(aspect));
         
-        let fovy_factor = self.size.height as f32 / self.camera.fovy * std::f32::consts::FRAC_PI_2 / 600.0;
-        self.fovy_factor.mutate(fovy_factor);
+        let camera_view = self.camera.view(aspect, self.size.height, self.min_flux);
+        self.fovy_factor.mutate(camera_view.fovy_factor() as f32);
 
         {
-            // send camera position to visibility thread, return immediately if visibility thread shutting down
+            // send camera view to visibility thread, return immediately if visibility thread shutting down
             let Some(tx) = self.vis_tx.as_ref() else { return Ok(()); };
-            let Ok(_) = tx.send((self.camera.transform.translation, self.camera.fovy, self.size.height)) else { return Ok(()); };
+            let Ok(_) = tx.send(camera_view) else { return Ok(()); };
         }
 
         if let Ok(v) = self.vis_rx.try_recv() {
@@ -475,11 +483,21 @@ impl<'a> State<'a> {
             render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
             render_pass.set_bind_group(1, &self.fovy_factor_bind_group, &[]);
 
-            for StarBuffer { model_bind_group, mesh, ..  } in &self.star_buffers {
+            for StarBuffer { model_bind_group, mesh, ..  } in self.star_buffers.iter().filter(|b| !b.resolved) {
                 render_pass.set_vertex_buffer(0, mesh.vertices.1.slice(..));
                 render_pass.set_bind_group(2, model_bind_group, &[]);
                 render_pass.draw(0..mesh.vertices.0, 0..instance_count);
             }
+
+            render_pass.set_pipeline(&self.disc_pipeline.0);
+
+            for StarBuffer { model_bind_group, mesh, ..  } in self.star_buffers.iter().filter(|b| b.resolved) {
+                let Some((num_indices, indices)) = &mesh.indices else { continue };
+                render_pass.set_vertex_buffer(0, mesh.vertices.1.slice(..));
+                render_pass.set_index_buffer(indices.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.set_bind_group(2, model_bind_group, &[]);
+                render_pass.draw_indexed(0..*num_indices, 0, 0..instance_count);
+            }
         }
 
         let Some(final_bind_group) = self.render_graph.as_ref().map(|g| g.render(&mut encoder)) else { panic!("lost render graph") };
@@ -523,6 +541,28 @@ impl<'a> State<'a> {
 struct Args {
     #[command(subcommand)]
     command: Option<Command>,
+    #[arg(long, default_value_t = tree::View::DEFAULT_MIN_FLUX, help="apparent brightness below which stars and sectors are not drawn")]
+    min_flux: f64,
+    #[arg(long, help="memory the loaded octree may use before the least recently seen cells are evicted to a temporary directory, in MiB, unlimited by default")]
+    memory_budget: Option<usize>,
+    #[arg(long, help="always populate the octree from the star catalogues, without reading or writing the octree cache")]
+    no_octree_cache: bool,
+    #[arg(long, default_value_t = 2000.0, help="julian year to move the stars to along their catalogued velocities")]
+    epoch: f64,
+    #[arg(long, default_value_t = tree::TreeConfig::DEFAULT.leaf_capacity, help="most stars an octree leaf holds before it is split")]
+    leaf_capacity: usize,
+    #[arg(long, default_value_t = tree::TreeConfig::DEFAULT.max_depth, help="deepest level of the octree, at most 30")]
+    max_depth: usize,
+    #[arg(long, default_value_t = tree::TreeConfig::DEFAULT.mesh_combine_threshold, help="visible sectors with fewer stars than this are drawn together with their parent")]
+    mesh_combine_threshold: usize,
+    #[arg(long, value_parser = parse_dvec3, allow_hyphen_values = true, help="camera start position as `x,y,z` in `--camera-unit`s, 3.24 parsecs above earth by default")]
+    camera_position: Option<glam::DVec3>,
+    #[arg(long, value_enum, default_value_t = LengthUnit::Parsecs, help="unit of `--camera-position`")]
+    camera_unit: LengthUnit,
+    #[arg(long, value_enum, default_value_t = universe::frame::Frame::Equatorial, help="celestial frame of `--camera-position`")]
+    camera_frame: universe::frame::Frame,
+    #[arg(long, help="read `--camera-position` as `longitude,latitude,distance`, with the angles in degrees")]
+    camera_spherical: bool,
 }
 
 #[derive(clap::Subcommand)]
@@ -534,7 +574,18 @@ enum Command {
         file_in: String,
         #[arg(help="output .bin catalogue file")]
         file_out: String,
-    }
+        #[arg(long, value_enum, default_value_t = universe::frame::Frame::Equatorial, help="celestial frame of the catalogue's positions and vectors")]
+        frame: universe::frame::Frame,
+    },
+    PageOctree {
+        #[arg(long, default_value_t = 4, help="number of octree levels stored in each paged cell")]
+        stride: usize,
+    },
+    ValidateOctree,
+    InspectOctree {
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
 }
 
 #[derive(Clone, Copy, clap::ValueEnum)]
@@ -542,24 +593,81 @@ enum CatalogueType {
     Stars,
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LengthUnit {
+    Metres,
+    Au,
+    LightYears,
+    Parsecs,
+}
+
+impl LengthUnit {
+    fn length(self, value: f64) -> units::Metres<FP128> {
+        match self {
+            LengthUnit::Metres => units::Metres(value).into(),
+            LengthUnit::Au => units::Au(value).into(),
+            LengthUnit::LightYears => units::LightYears(value).into(),
+            LengthUnit::Parsecs => units::Parsecs(value).into(),
+        }
+    }
+
+    fn position(self, value: glam::DVec3) -> units::Metres<Vec3F> {
+        match self {
+            LengthUnit::Metres => units::Metres(value).into(),
+            LengthUnit::Au => units::Au(value).into(),
+            LengthUnit::LightYears => units::LightYears(value).into(),
+            LengthUnit::Parsecs => units::Parsecs(value).into(),
+        }
+    }
+}
+
+fn parse_dvec3(s: &str) -> Result<glam::DVec3, String> {
+    let components = s.split(',').map(|c| c.trim().parse::<f64>().map_err(|e| format!("`{c}`: {e}"))).collect::<Result<Vec<_>, _>>()?;
+    match components[..] {
+        [x, y, z] => Ok(glam::dvec3(x, y, z)),
+        _ => Err(format!("expected 3 comma separated numbers, found {}", components.len())),
+    }
+}
+
 async fn run() -> anyhow::Result<()> {
-    let Args { command } = Args::parse();
+    let Args { command, min_flux, memory_budget, no_octree_cache, epoch, leaf_capacity, max_depth, mesh_combine_threshold, camera_position, camera_unit, camera_frame, camera_spherical } = Args::parse();
+
+    if leaf_capacity == 0 {
+        return Err(anyhow::anyhow!("Leaf capacity should be at least 1."));
+    }
+    if max_depth > tree::Cell::MAX_DEPTH {
+        return Err(anyhow::anyhow!("Max depth should be at most {}.", tree::Cell::MAX_DEPTH));
+    }
+    let config = tree::TreeConfig { leaf_capacity, max_depth, mesh_combine_threshold };
 
     if let Some(command) = command {
-        return run_command(command);
+        return run_command(command, !no_octree_cache, config);
     }
-    
-    // print!("loading root cell... ");
-    // let start = Instant::now();
-    // let cell = bincode::deserialize_from::<_, Cell>(GzDecoder::new(File::open("data/cells/cell_7.bin.gz").unwrap())).unwrap();
-    // println!("done ({:?})", Instant::now() - start);
 
-    let universe = universe::Universe::new()?;
+    let mut universe = universe::Universe::new(!no_octree_cache, config)?;
+    universe.set_memory_budget(memory_budget.map(|m| m * 1024 * 1024));
+    if epoch != 2000.0 {
+        let moved = universe.set_epoch((epoch - 2000.0) * universe::JULIAN_YEAR);
+        log::info!("moved {moved} stars to epoch J{epoch}");
+    }
 
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
-    let mut state = State::new(&window, universe).await;
+    let camera_position: units::Metres<Vec3F> = match camera_position {
+        Some(glam::DVec3 { x: lon, y: lat, z: dist }) if camera_spherical => camera_frame.position(lon, lat, camera_unit.length(dist)),
+        Some(position) => camera_unit.position(camera_frame.to_engine(position)),
+        // just off the line between earth and the celestial north pole
+        None => units::Metres::from(units::Au(glam::dvec3(1.0314, 0.0, 0.0))) + units::Metres::from(units::Parsecs(glam::dvec3(0.0, 0.0, 3.2408))),
+    };
+
+    let mut state = State::new(&window, universe, min_flux, camera_position).await;
 
     event_loop.run(move |event, event_loop| {
         match event {
@@ -621,9 +729,9 @@ async fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn run_command(command: Command) -> anyhow::Result<()> {
+fn run_command(command: Command, octree_cache: bool, config: tree::TreeConfig) -> anyhow::Result<()> {
     match command {
-        Command::EncodeCatalogue { cat_type, file_in, file_out } => {
+        Command::EncodeCatalogue { cat_type, file_in, file_out, frame } => {
             if !file_in.ends_with(".csv") {
                 return Err(anyhow::anyhow!("Input file path should end with `.csv`."));
             }
@@ -637,11 +745,12 @@ fn run_command(command: Command) -> anyhow::Result<()> {
                         File::open(file_in)?
                     );
 
-                    let catalogue = universe::StarCatalogue::from_csv(reader)?;
+                    let catalogue = universe::StarCatalogue::from_csv(reader, frame)?;
                     eprintln!("done");
 
                     eprint!("encoding...");
-                    let data = bincode::serialize(&catalogue)?;
+                    let mut data = vec![];
+                    catalogue.encode(&mut data)?;
                     eprintln!("done");
 
                     eprint!("compressing...");
@@ -650,6 +759,44 @@ fn run_command(command: Command) -> anyhow::Result<()> {
                 },
             }
         },
+        Command::PageOctree { stride } => {
+            if stride == 0 {
+                return Err(anyhow::anyhow!("Stride should be at least 1."));
+            }
+
+            let mut universe = universe::Universe::from_catalogues(octree_cache, config)?;
+
+            eprint!("paging octree...");
+            universe.page_out(stride)?;
+            eprintln!("done");
+        },
+        Command::ValidateOctree => {
+            let universe = universe::Universe::new(octree_cache, config)?;
+
+            eprint!("validating octree...");
+            let violations = universe.validate();
+            eprintln!("done");
+
+            for violation in &violations {
+                println!("{violation}");
+            }
+
+            if !violations.is_empty() {
+                return Err(anyhow::anyhow!("Found {} octree invariant violations.", violations.len()));
+            }
+        },
+        Command::InspectOctree { format } => {
+            let universe = universe::Universe::new(octree_cache, config)?;
+
+            eprint!("inspecting octree...");
+            let stats = universe.stats();
+            eprintln!("done");
+
+            match format {
+                ReportFormat::Text => print!("{stats}"),
+                ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+            }
+        },
     }
 
     Ok(())
@@ -1,4 +1,4 @@
-use crate::transform::Transform;
+use crate::{fp::Vec3F, transform::Transform, tree::View};
 
 pub struct Camera {
     pub transform: Transform,
@@ -21,4 +21,35 @@ impl Camera {
 
         perspective * view
     }
+
+    pub fn view(&self, aspect: f32, screen_height: u32, min_flux: f64) -> View {
+        View {
+            position: self.transform.translation,
+            rotation: self.transform.rotation,
+            fovy: self.fovy,
+            aspect,
+            screen_height,
+            min_flux,
+        }
+    }
+
+    /// origin and normalised direction of the ray through a pixel, `cursor` is in pixels from the top left
+    #[allow(unused)]
+    pub fn pick_ray(&self, cursor: glam::Vec2, screen_size: glam::UVec2) -> (Vec3F, glam::DVec3) {
+        let ndc = cursor / screen_size.as_vec2() * glam::vec2(2.0, -2.0) + glam::vec2(-1.0, 1.0);
+        let aspect = screen_size.x as f32 / screen_size.y as f32;
+        let tan = (self.fovy / 2.0).tan();
+
+        let view_dir = glam::vec3(ndc.x * tan * aspect, ndc.y * tan, -1.0);
+        // the view matrix rotates the world by `rotation`, so undo it
+        let dir = self.transform.rotation.inverse() * view_dir;
+
+        (self.transform.translation, dir.as_dvec3().normalize())
+    }
+
+    /// angle covered by a single pixel, a good tolerance for picking
+    #[allow(unused)]
+    pub fn pixel_angle(&self, screen_height: u32) -> f64 {
+        self.fovy as f64 / screen_height as f64
+    }
 }
@@ -33,4 +33,25 @@ impl Mesh {
             }))),
         }
     }
+
+    /// icosahedron approximating a sphere of the given radius around the origin, faces wind counter-clockwise seen from outside
+    pub fn sphere(renderer: &Renderer, radius: f32, colour: glam::Vec3) -> Self {
+        let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+        let vertices = [
+            (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+            (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+            (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+        ].map(|(x, y, z)| Vertex {
+            position: glam::vec3(x, y, z).normalize() * radius,
+            colour,
+        });
+        let indices = [
+            0, 11, 5, 0, 5, 1, 0, 1, 7, 0, 7, 10, 0, 10, 11,
+            1, 5, 9, 5, 11, 4, 11, 10, 2, 10, 7, 6, 7, 1, 8,
+            3, 9, 4, 3, 4, 2, 3, 2, 6, 3, 6, 8, 3, 8, 9,
+            4, 9, 5, 2, 4, 11, 6, 2, 10, 8, 6, 7, 9, 8, 1,
+        ];
+
+        Self::with_indices(renderer, &vertices, &indices)
+    }
 }
@@ -0,0 +1,228 @@
+use std::{fmt, iter::Sum, ops::{Add, AddAssign, Div, Mul, MulAssign, Sub, SubAssign}};
+
+/// power emitted in each of `Spectrum::BINS` equal width wavelength bins across the visible range, in the engine's luminosity units.
+/// spectra add linearly, so a sector's spectrum is the sum of its bodies', and only become colours in `Spectrum::to_rgb`.
+/// the bins are `f64`, making a spectrum 64 bytes in every body and sector: the brightest stars are around 1e40 in these units,
+/// past `f32::MAX`, and a sector's luminosity is checked against the sum of its children's to 1e-9, finer than `f32` resolves
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct Spectrum([f64; Spectrum::BINS]);
+
+#[allow(unused)]
+impl Spectrum {
+    pub const BINS: usize = 8;
+    /// shortest wavelength covered, in nanometres
+    pub const MIN_WAVELENGTH: f64 = 380.0;
+    /// longest wavelength covered, in nanometres
+    pub const MAX_WAVELENGTH: f64 = 780.0;
+    pub const BIN_WIDTH: f64 = (Self::MAX_WAVELENGTH - Self::MIN_WAVELENGTH) / Self::BINS as f64;
+
+    pub const ZERO: Self = Self::splat(0.0);
+    pub const ONE: Self = Self::splat(1.0);
+
+    /// fraction of each bin seen by the red, green and blue channels of `to_rgb`.
+    /// the same bands (r 520-630nm, g 500-590nm, b 410-480nm) the engine always used to colour stars
+    const RGB_RESPONSE: [[f64; Self::BINS]; 3] = [
+        [0.0, 0.0, 0.2, 1.0, 1.0, 0.0, 0.0, 0.0],
+        [0.0, 0.0, 0.6, 1.0, 0.2, 0.0, 0.0, 0.0],
+        [0.4, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    ];
+
+    pub const fn new(bins: [f64; Self::BINS]) -> Self {
+        Self(bins)
+    }
+
+    pub const fn splat(power: f64) -> Self {
+        Self([power; Self::BINS])
+    }
+
+    pub const fn bins(&self) -> [f64; Self::BINS] {
+        self.0
+    }
+
+    /// shortest and longest wavelength of a bin, in nanometres
+    pub fn wavelengths(bin: usize) -> (f64, f64) {
+        let min = Self::MIN_WAVELENGTH + bin as f64 * Self::BIN_WIDTH;
+        (min, min + Self::BIN_WIDTH)
+    }
+
+    /// spectrum of a black body at `temp` kelvin, scaled so its brightest display channel is `brightness`
+    pub fn black_body(temp: f64, brightness: f64) -> Self {
+        // second radiation constant hc/k, in nanometre kelvin
+        const C2: f64 = 1.438777e7;
+        // samples per bin, planck's law is smooth enough over 50nm
+        const SAMPLES: usize = 4;
+
+        let mut spectrum = Self::ZERO;
+        for (bin, power) in spectrum.0.iter_mut().enumerate() {
+            let (min, _) = Self::wavelengths(bin);
+            *power = (0..SAMPLES).map(|i| {
+                let wavelength = min + (i as f64 + 0.5) * Self::BIN_WIDTH / SAMPLES as f64;
+                // relative to 500nm, to keep the numbers reasonable
+                let x = wavelength / 500.0;
+                1.0 / (x.powi(5) * (C2 / (wavelength * temp)).exp_m1())
+            }).sum::<f64>() / SAMPLES as f64;
+        }
+
+        let peak = spectrum.to_rgb().max_element();
+        if peak > 0.0 && peak.is_finite() {
+            spectrum * (brightness / peak)
+        } else {
+            Self::ZERO
+        }
+    }
+
+    /// total power over all bins
+    pub fn total(&self) -> f64 {
+        self.0.iter().sum()
+    }
+
+    pub fn max_element(&self) -> f64 {
+        self.0.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    pub fn abs(&self) -> Self {
+        Self(self.0.map(f64::abs))
+    }
+
+    /// colour to display, before any attenuation with distance
+    pub fn to_rgb(self) -> glam::DVec3 {
+        let [r, g, b] = Self::RGB_RESPONSE.map(|response| response.iter().zip(self.0).map(|(r, p)| r * p).sum());
+        glam::dvec3(r, g, b)
+    }
+}
+
+impl fmt::Display for Spectrum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, power) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{power}")?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl Add for Spectrum {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for Spectrum {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a += b);
+    }
+}
+
+impl Sub for Spectrum {
+    type Output = Self;
+
+    fn sub(mut self, rhs: Self) -> Self::Output {
+        self -= rhs;
+        self
+    }
+}
+
+impl SubAssign for Spectrum {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a -= b);
+    }
+}
+
+impl Mul<f64> for Spectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        Self(self.0.map(|p| p * rhs))
+    }
+}
+
+impl MulAssign<f64> for Spectrum {
+    fn mul_assign(&mut self, rhs: f64) {
+        *self = *self * rhs;
+    }
+}
+
+impl Div<f64> for Spectrum {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        Self(self.0.map(|p| p / rhs))
+    }
+}
+
+/// per bin, e.g. to apply a transmission curve
+impl Mul for Spectrum {
+    type Output = Self;
+
+    fn mul(mut self, rhs: Self) -> Self::Output {
+        self *= rhs;
+        self
+    }
+}
+
+impl MulAssign for Spectrum {
+    fn mul_assign(&mut self, rhs: Self) {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a *= b);
+    }
+}
+
+/// per bin, bins with no power in `rhs` are zero
+impl Div for Spectrum {
+    type Output = Self;
+
+    fn div(mut self, rhs: Self) -> Self::Output {
+        self.0.iter_mut().zip(rhs.0).for_each(|(a, b)| *a = if b == 0.0 { 0.0 } else { *a / b });
+        self
+    }
+}
+
+impl Sum for Spectrum {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn black_body() {
+        let sun = Spectrum::black_body(5800.0, 3.0);
+        let rgb = sun.to_rgb();
+        assert!((rgb.max_element() - 3.0).abs() < 1e-12);
+        assert!(sun.bins().iter().all(|p| *p > 0.0));
+
+        // cool stars are red, hot stars are blue
+        let cool = Spectrum::black_body(3000.0, 1.0).to_rgb();
+        let hot = Spectrum::black_body(20000.0, 1.0).to_rgb();
+        assert!(cool.x > cool.z && hot.z > hot.x);
+
+        // too cold to emit any visible light
+        assert_eq!(Spectrum::black_body(10.0, 1.0), Spectrum::ZERO);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = Spectrum::new([1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+
+        assert_eq!(a.total(), 36.0);
+        assert_eq!(a.max_element(), 8.0);
+        assert_eq!(a + a, a * 2.0);
+        assert_eq!(a - a, Spectrum::ZERO);
+        assert_eq!((a * 2.0) / 2.0, a);
+        assert_eq!(a / a, Spectrum::ONE);
+        assert_eq!(a / Spectrum::ZERO, Spectrum::ZERO);
+        assert_eq!((Spectrum::ZERO - a).abs(), a);
+        assert_eq!([a, a, a].into_iter().sum::<Spectrum>(), a * 3.0);
+
+        // linear, so summing spectra sums colours
+        assert!(((a + a).to_rgb() - a.to_rgb() * 2.0).abs().max_element() < 1e-12);
+    }
+}
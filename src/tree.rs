@@ -1,26 +1,114 @@
-use std::{fmt::Debug, hash::Hash};
+use std::{cmp::Ordering, collections::BinaryHeap, fmt::Debug, hash::Hash};
 
 use fixed::traits::ToFixed;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
 
-use crate::fp::{Vec3F, FP128};
+use crate::{fp::{Aabb, Vec3F, FP128}, spectrum::Spectrum};
 
+/// anything that can be stored in an octree
+pub trait Body: Clone + Debug + Send + Sync + serde::Serialize + serde::de::DeserializeOwned {
+    fn position(&self) -> Vec3F;
+    fn set_position(&mut self, position: Vec3F);
+    /// diameter of the body's visible extent
+    fn diameter(&self) -> FP128;
+    fn luminosity(&self) -> Spectrum;
+    fn kind(&self) -> BodyKind;
+    /// in metres per second, see `Cell::propagate`
+    fn velocity(&self) -> glam::DVec3 {
+        glam::DVec3::ZERO
+    }
+}
+
+/// what a `PointLight` was drawn from, a kind is added along with the `Body` type that reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum BodyKind {
+    Star,
+    Galaxy,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Star {
+    pub position: Vec3F,
+    pub radius: FP128,
+    pub spectrum: Spectrum,
+    pub velocity: glam::DVec3,
+}
+
+impl Star {
+    /// a star at rest, see `Cell::propagate`
+    pub fn new(position: Vec3F, radius: FP128, spectrum: Spectrum) -> Self {
+        Star { position, radius, spectrum, velocity: glam::DVec3::ZERO }
+    }
+}
+
+/// a galaxy seen from outside as a single body, from `GalaxyCatalogue::bodies`
+#[allow(unused)]
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Body {
+pub struct Galaxy {
     pub position: Vec3F,
-    pub colour: glam::DVec3,
+    pub diameter: FP128,
+    pub spectrum: Spectrum,
+}
+
+impl Body for Galaxy {
+    fn position(&self) -> Vec3F {
+        self.position
+    }
+
+    fn set_position(&mut self, position: Vec3F) {
+        self.position = position;
+    }
+
+    fn diameter(&self) -> FP128 {
+        self.diameter
+    }
+
+    fn luminosity(&self) -> Spectrum {
+        self.spectrum
+    }
+
+    fn kind(&self) -> BodyKind {
+        BodyKind::Galaxy
+    }
+}
+
+/// `n` stars at rest scattered within `half_size` of the origin, the same for the same `seed`.
+/// their spectra are whole numbers, so luminosity sums are exact whatever order they are added in
+#[cfg(test)]
+pub fn random_stars(seed: u64, n: usize, half_size: f64) -> Vec<Star> {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+    (0..n).map(|_| Star::new(
+        Vec3F::from_f64s(rng.gen_range(-half_size..half_size), rng.gen_range(-half_size..half_size), rng.gen_range(-half_size..half_size)),
+        0.5.to_fixed(),
+        Spectrum::new([rng.gen_range(0..4) as f64, rng.gen_range(0..4) as f64, 1.0, 0.0, 1.0, 2.0, 3.0, 4.0]),
+    )).collect()
 }
 
-impl Body {
+impl Body for Star {
     fn position(&self) -> Vec3F {
         self.position
     }
+
+    fn set_position(&mut self, position: Vec3F) {
+        self.position = position;
+    }
     
     fn diameter(&self) -> FP128 {
-        1.0.to_fixed()
+        self.radius * 2
+    }
+
+    fn luminosity(&self) -> Spectrum {
+        self.spectrum
+    }
+
+    fn kind(&self) -> BodyKind {
+        BodyKind::Star
     }
 
-    fn luminosity(&self) -> glam::DVec3 {
-        self.colour
+    fn velocity(&self) -> glam::DVec3 {
+        self.velocity
     }
 }
 
@@ -92,57 +180,84 @@ impl From<(bool, bool, bool)> for Octant {
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Sector {
     id: u128,
-    /// (min, max)
-    // bounds: (Vec3F, Vec3F),
-    half: Vec3F,
-    centre: Vec3F,
-    luminosity: glam::DVec3,
+    bounds: Aabb,
+    luminosity: Spectrum,
+    /// moments of the bodies below this sector, luminosity not belonging to any body has no position
+    moments: Moments,
     depth: usize,
 }
 
 impl Sector {
     #[allow(unused)]
-    fn new(id: u128, bound_min: Vec3F, bound_max: Vec3F, luminosity: glam::DVec3) -> Self {
-        Self::with_depth(id, bound_min, bound_max, luminosity, 0)
+    fn new(id: u128, bounds: Aabb, luminosity: Spectrum) -> Self {
+        Self::with_depth(id, bounds, luminosity, 0)
     }
     
-    fn with_depth(id: u128, bound_min: Vec3F, bound_max: Vec3F, luminosity: glam::DVec3, depth: usize) -> Self {
-        let centre = (bound_min + bound_max) / 2.0;
+    fn with_depth(id: u128, bounds: Aabb, luminosity: Spectrum, depth: usize) -> Self {
         Self {
             id,
-            // bounds: (bound_min, bound_max),
-            half: bound_max - centre,
-            centre,
+            bounds,
             luminosity,
+            moments: Moments::default(),
             depth,
         }
     }
 
     fn octant(&self, point: Vec3F) -> Option<Octant> {
-        // let (min, max) = self.bounds;
-        let min = self.centre - self.half;
-        let max = self.centre + self.half;
-        if point.x < min.x || point.y < min.y || point.z < min.z || max.x <= point.x || max.y <= point.y || max.z <= point.z {
-            return None;
-        }
+        self.bounds.octant_index(point).map(|i| Octant::ALL[i])
+    }
 
-        Some(Octant::from((point.x >= self.centre.x, point.y >= self.centre.y, point.z >= self.centre.z)))
+    /// bounds of one of this sector's children
+    fn child_bounds(&self, octant: Octant) -> Aabb {
+        self.bounds.octant(octant as usize)
+    }
+
+    /// where the sector splits into octants, moments are measured from here
+    fn centre(&self) -> Vec3F {
+        self.bounds.centre()
     }
 
     fn dimensions(&self) -> Vec3F {
-        // self.bounds.1 - self.bounds.0
-        self.half * 2.0
+        self.bounds.size()
     }
 
     pub fn id(&self) -> u128 {
         self.id
     }
 
-    pub fn luminosity(&self) -> glam::DVec3 {
-        self.luminosity
+    /// levels below the root, which is at 0
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// luminosity weighted centre of the bodies in this sector, the sector centre if it has none
+    pub fn centroid(&self) -> Vec3F {
+        match self.moments.centroid() {
+            Some(offset) => self.centre() + Vec3F::from_dvec3(offset),
+            None => self.centre(),
+        }
+    }
+
+    /// diameter of the region the sector's light appears to come from, twice the weighted rms distance of its bodies from the centroid
+    pub fn spread(&self) -> FP128 {
+        if self.moments.weight <= 0.0 {
+            return self.dimensions().max_element();
+        }
+        (self.moments.spread() * 2.0).to_fixed()
+    }
+
+    /// account for the light of a body inside this sector
+    fn add_light(&mut self, body: &impl Body) {
+        self.luminosity += body.luminosity();
+        self.moments += Moments::of((body.position() - self.centre()).to_dvec3(), body.luminosity().total());
+    }
+
+    fn remove_light(&mut self, body: &impl Body) {
+        self.luminosity -= body.luminosity();
+        self.moments -= Moments::of((body.position() - self.centre()).to_dvec3(), body.luminosity().total());
     }
 
-    const ID_ROOT: u128 = 0b111;
+    pub const ID_ROOT: u128 = 0b111;
 
     fn calc_id(tree_coord: &[Octant]) -> u128 {
         // all id's start with 111 (7) as a marker
@@ -168,51 +283,232 @@ impl Sector {
     fn id_push(id: u128, oct: Octant) -> u128 {
         id * 8 + oct as u128
     }
+
+    /// id of the sector at `Cell::MAX_DEPTH` containing `point`, as if this sector were the root.
+    /// ordering bodies by this id orders them by their octant path through the tree
+    fn path_id(&self, point: Vec3F) -> Option<u128> {
+        let mut path = [Octant::NxNyNz; Cell::MAX_DEPTH];
+        let path = &mut path[..Cell::MAX_DEPTH - self.depth];
+        let mut bounds = self.bounds;
+
+        for oct in path.iter_mut() {
+            let index = bounds.octant_index(point)?;
+            *oct = Octant::ALL[index];
+            bounds = bounds.octant(index);
+        }
+
+        Some(Self::calc_id(path))
+    }
+}
+/// luminosity weighted moments of a set of bodies, offsets are measured from the centre of the sector holding them.
+/// bodies are weighted by the sum of their luminosity channels. the spread is kept about the centroid rather than
+/// the sector centre, so tight clusters far from the centre of a large sector don't lose it to cancellation
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Moments {
+    weight: f64,
+    /// weighted mean offset
+    mean: glam::DVec3,
+    /// sum of weighted squared distances from the mean
+    m2: f64,
+}
+
+impl Moments {
+    // relative to the moments of a sphere around the sector, sums are accumulated in different orders
+    const TOLERANCE: f64 = 1e-9;
+
+    fn of(offset: glam::DVec3, weight: f64) -> Self {
+        Self {
+            weight,
+            mean: offset,
+            m2: 0.0,
+        }
+    }
+
+    /// the same moments measured from a point `by` behind the current origin, so every offset `o` becomes `o + by`
+    fn shifted(self, by: glam::DVec3) -> Self {
+        Self {
+            mean: self.mean + by,
+            ..self
+        }
+    }
+
+    fn centroid(&self) -> Option<glam::DVec3> {
+        (self.weight > 0.0).then_some(self.mean)
+    }
+
+    /// weighted rms distance from the centroid
+    fn spread(&self) -> f64 {
+        if self.weight <= 0.0 {
+            return 0.0;
+        }
+        (self.m2 / self.weight).max(0.0).sqrt()
+    }
+
+    /// whether two sets of moments of bodies within `radius` of the origin agree
+    fn approx_eq(&self, other: &Self, radius: f64) -> bool {
+        let weight = self.weight.abs().max(other.weight.abs());
+        (self.weight - other.weight).abs() <= weight * Self::TOLERANCE &&
+        (self.mean * self.weight - other.mean * other.weight).length() <= weight * radius * Self::TOLERANCE &&
+        (self.m2 - other.m2).abs() <= weight * radius * radius * Self::TOLERANCE
+    }
+}
+
+impl std::ops::AddAssign for Moments {
+    // parallel form of welford's algorithm
+    fn add_assign(&mut self, rhs: Self) {
+        let weight = self.weight + rhs.weight;
+        if weight <= 0.0 {
+            *self = Self { weight, ..Default::default() };
+            return;
+        }
+        let delta = rhs.mean - self.mean;
+        self.m2 += rhs.m2 + delta.length_squared() * self.weight * rhs.weight / weight;
+        self.mean += delta * (rhs.weight / weight);
+        self.weight = weight;
+    }
+}
+
+impl std::ops::SubAssign for Moments {
+    // inverse of `add_assign`
+    fn sub_assign(&mut self, rhs: Self) {
+        let weight = self.weight - rhs.weight;
+        if weight <= 0.0 {
+            *self = Self::default();
+            return;
+        }
+        let mean = (self.mean * self.weight - rhs.mean * rhs.weight) / weight;
+        let delta = rhs.mean - mean;
+        self.m2 -= rhs.m2 + delta.length_squared() * weight * rhs.weight / self.weight;
+        self.mean = mean;
+        self.weight = weight;
+    }
 }
+
 // u128 capable of holding cell id's
 const_assert!(Cell::MAX_DEPTH*3 < 128);
 
+// leaves far outnumber cells, boxing them would cost an allocation each
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
-enum Node {
-    Cell(Box<Cell>),
-    Leaf(Leaf),
+enum Node<B> {
+    Cell(Box<Cell<B>>),
+    Leaf(Leaf<B>),
     Unloaded(u128),
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct Leaf {
+struct Leaf<B> {
     sector: Sector,
-    children: Vec<Body>,
+    children: Vec<B>,
 }
 
-impl Leaf {
+impl<B> Leaf<B> {
     #[allow(unused)]
-    fn new(bound_min: Vec3F, bound_max: Vec3F, luminosity: glam::DVec3) -> Self {
-        Self::with_depth(bound_min, bound_max, luminosity, 0, Sector::ID_ROOT)
+    fn new(bounds: Aabb, luminosity: Spectrum) -> Self {
+        Self::with_depth(bounds, luminosity, 0, Sector::ID_ROOT)
     }
     
-    fn with_depth(bound_min: Vec3F, bound_max: Vec3F, luminosity: glam::DVec3, depth: usize, id: u128) -> Self {
+    fn with_depth(bounds: Aabb, luminosity: Spectrum, depth: usize, id: u128) -> Self {
         Self {
-            sector: Sector::with_depth(id, bound_min, bound_max, luminosity, depth),
+            sector: Sector::with_depth(id, bounds, luminosity, depth),
             children: Vec::new(),
         }
     }
 }
 
+/// where the universe is being viewed from, see `render::Camera::view`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub position: Vec3F,
+    /// rotation applied to the world by the view matrix
+    pub rotation: glam::Quat,
+    pub fovy: f32,
+    pub aspect: f32,
+    pub screen_height: u32,
+    /// flux below which sectors and bodies are not drawn, see `View::flux`
+    pub min_flux: f64,
+}
+
+impl View {
+    // this value is purposefully extremely small, we want the leaf nodes to show even if there is only the slightest chance they will be visible,
+    // especially given that point lights use additive blending, they may still be visible if overlapping
+    pub const DEFAULT_MIN_FLUX: f64 = 0.01 / 255.0;
+
+    /// brightness scale applied for the field of view, shared with the fragment shader
+    pub fn fovy_factor(&self) -> f64 {
+        self.screen_height as f64 / self.fovy as f64 * std::f64::consts::FRAC_PI_2 / 600.0
+    }
+
+    /// apparent brightness of a light seen from `dist` away, the brightest channel `fs_main` in shaders/shader.wgsl draws it with
+    pub fn flux(&self, luminosity: Spectrum, dist: f64) -> f64 {
+        let att = 1.0 + dist.max(0.0);
+        luminosity.to_rgb().max_element() / (att * att) * self.fovy_factor()
+    }
+
+    /// height in pixels of something `diameter` across seen from `dist` away
+    pub fn pixel_size(&self, diameter: f64, dist: f64) -> f64 {
+        diameter / dist.max(f64::MIN_POSITIVE) * self.screen_height as f64 / self.fovy as f64
+    }
+
+    /// extra angle around the frustum (as a fraction of fovy) still treated as visible, so bloom from bright stars just off screen isn't lost
+    pub const FRUSTUM_MARGIN: f32 = 0.1;
+
+    /// whether any part of the sector's bounding sphere may be inside the (infinite) view frustum
+    fn in_frustum(&self, sector: &Sector) -> bool {
+        let radius = sector.bounds.radius().to_num::<f64>();
+        let centre = (self.rotation.as_dquat()) * (sector.centre() - self.position).to_dvec3();
+
+        // camera inside bounding sphere
+        if centre.length_squared() <= radius * radius {
+            return true;
+        }
+
+        let margin = self.fovy * Self::FRUSTUM_MARGIN;
+        let half_y = (self.fovy / 2.0 + margin).min(std::f32::consts::FRAC_PI_2) as f64;
+        let half_x = (((self.fovy / 2.0).tan() * self.aspect).atan() + margin).min(std::f32::consts::FRAC_PI_2) as f64;
+
+        // inward facing normals of the side planes, the camera looks down -z
+        let (sin_y, cos_y) = half_y.sin_cos();
+        let (sin_x, cos_x) = half_x.sin_cos();
+        let planes = [
+            glam::dvec3(0.0, -cos_y, -sin_y),
+            glam::dvec3(0.0, cos_y, -sin_y),
+            glam::dvec3(-cos_x, 0.0, -sin_x),
+            glam::dvec3(cos_x, 0.0, -sin_x),
+        ];
+
+        planes.iter().all(|n| n.dot(centre) >= -radius)
+    }
+}
+
+/// how much of a cell the visibility pass should draw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lod {
+    Culled,
+    Aggregate,
+    Resolve,
+}
+
 #[derive(Debug, Clone)]
 pub struct PointLight {
     pub position: Vec3F,
     pub diameter: FP128,
-    pub colour: glam::DVec3,
-    pub is_body: bool,
+    pub spectrum: Spectrum,
+    /// `None` for the combined light of a sector
+    pub kind: Option<BodyKind>,
+    /// body is close enough to be drawn as a disc rather than a point, see `PointLight::MIN_DISC_PIXELS`
+    pub resolved: bool,
+}
+
+impl PointLight {
+    /// apparent diameter at which a body stops being drawn as a single point
+    pub const MIN_DISC_PIXELS: f64 = 1.0;
 }
 
 impl PartialEq for PointLight {
     fn eq(&self, other: &Self) -> bool {
-        self.position == other.position && self.diameter == other.diameter &&
-        self.colour.x as u128 == other.colour.x as u128 &&
-        self.colour.y as u128 == other.colour.y as u128 &&
-        self.colour.z as u128 == other.colour.z as u128
+        self.position == other.position && self.diameter == other.diameter && self.kind == other.kind && self.resolved == other.resolved &&
+        self.spectrum.bins().map(|p| p as u128) == other.spectrum.bins().map(|p| p as u128)
     }
 }
 
@@ -222,49 +518,186 @@ impl Hash for PointLight {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.position.hash(state);
         self.diameter.hash(state);
-        (self.colour.x as u128).hash(state);
-        (self.colour.y as u128).hash(state);
-        (self.colour.z as u128).hash(state);
+        self.kind.hash(state);
+        self.resolved.hash(state);
+        self.spectrum.bins().map(|p| p as u128).hash(state);
+    }
+}
+
+/// broken octree invariant found by `Cell::validate`
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum Violation {
+    #[error("body at {position:?} is outside the bounds of sector {id}")]
+    OutOfBounds { id: u128, position: Vec3F },
+    #[error("sector {id} should have id {expected} ({:?})", Sector::tree_coord(*expected))]
+    IdMismatch { id: u128, expected: u128 },
+    #[error("sector {id} has depth {depth}, expected {expected}")]
+    DepthMismatch { id: u128, depth: usize, expected: usize },
+    #[error("sector {id} does not cover its octant of the parent sector")]
+    BoundsMismatch { id: u128 },
+    #[error("sector {id} has luminosity {luminosity}, but its children sum to {children}")]
+    LuminosityMismatch { id: u128, luminosity: Spectrum, children: Spectrum },
+    #[error("leaf {id} holds {bodies} bodies, more than its capacity above the maximum depth")]
+    Overfull { id: u128, bodies: usize },
+    #[error("sector {id} has a centroid or spread that does not match its bodies")]
+    MomentsMismatch { id: u128 },
+}
+
+/// loaded cell that may be evicted, see `Cell::evict`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvictionCandidate {
+    pub id: u128,
+    pub depth: usize,
+    /// visibility query that last reached the cell
+    pub visited: u64,
+}
+
+/// summary of the shape of a tree, see `Cell::stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct TreeStats {
+    /// nodes and bodies at each depth, indexed by depth
+    pub depths: Vec<DepthStats>,
+    /// number of leaves holding each number of bodies
+    pub leaf_occupancy: std::collections::BTreeMap<usize, usize>,
+    /// depth of the deepest leaves the tree may have, see `TreeConfig::max_depth`
+    pub max_depth: usize,
+    /// most bodies in a single leaf at `max_depth`
+    pub max_leaf_occupancy: usize,
+    pub cells: usize,
+    pub leaves: usize,
+    pub unloaded: usize,
+    pub bodies: usize,
+    /// estimated memory used by the loaded tree, in bytes
+    pub memory: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DepthStats {
+    pub cells: usize,
+    pub leaves: usize,
+    pub unloaded: usize,
+    pub bodies: usize,
+}
+
+impl TreeStats {
+    fn count_at(&mut self, depth: usize) -> &mut DepthStats {
+        if self.depths.len() <= depth {
+            self.depths.resize(depth + 1, DepthStats::default());
+        }
+        &mut self.depths[depth]
+    }
+}
+
+impl std::fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "cells: {}, leaves: {}, unloaded: {}, bodies: {}", self.cells, self.leaves, self.unloaded, self.bodies)?;
+        writeln!(f, "estimated memory: {:.1} MiB", self.memory as f64 / (1024.0 * 1024.0))?;
+        writeln!(f, "max leaf occupancy at depth {}: {}", self.max_depth, self.max_leaf_occupancy)?;
+
+        writeln!(f)?;
+        writeln!(f, "{:>5} {:>10} {:>10} {:>10} {:>10}", "depth", "cells", "leaves", "unloaded", "bodies")?;
+        for (depth, stats) in self.depths.iter().enumerate() {
+            writeln!(f, "{:>5} {:>10} {:>10} {:>10} {:>10}", depth, stats.cells, stats.leaves, stats.unloaded, stats.bodies)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:>6} {:>10}", "bodies", "leaves")?;
+        for (bodies, leaves) in &self.leaf_occupancy {
+            writeln!(f, "{:>6} {:>10}", bodies, leaves)?;
+        }
+
+        Ok(())
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CellVisibility {
+    /// id of the sector these lights were gathered from, at most one per sector is visible at a time
+    pub id: u128,
     pub centre: Vec3F,
     pub depth: usize,
     pub bodies: Vec<PointLight>,
 }
 
+/// how a tree splits and draws its cells, shared by every cell in the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TreeConfig {
+    /// most bodies a leaf above `max_depth` holds before it is split into a cell
+    pub leaf_capacity: usize,
+    /// depth of the deepest leaves, which hold any number of bodies. at most `Cell::MAX_DEPTH`
+    pub max_depth: usize,
+    /// visible cells with fewer points than this are drawn as part of their parent's mesh
+    pub mesh_combine_threshold: usize,
+}
+
+impl TreeConfig {
+    pub const DEFAULT: TreeConfig = TreeConfig {
+        leaf_capacity: 1,
+        max_depth: Cell::MAX_DEPTH,
+        mesh_combine_threshold: 8192*16,
+    };
+
+    fn check(&self) {
+        assert!(self.leaf_capacity > 0, "leaf capacity must be at least 1");
+        assert!(self.max_depth <= Cell::MAX_DEPTH, "max depth {} is deeper than {}", self.max_depth, Cell::MAX_DEPTH);
+    }
+}
+
+impl Default for TreeConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct Cell {
+pub struct Cell<B = Star> {
     sector: Sector,
-    children: [Node; 8],
+    children: [Node<B>; 8],
+    /// visibility query that last reached this cell, see `Cell::evict`
+    #[serde(skip)]
+    visited: u64,
+    /// returned by `load_cell` itself, rather than loaded as part of an ancestor
+    #[serde(skip)]
+    loaded: bool,
+    /// unmodified since it was loaded, along with everything loaded with it
+    #[serde(skip)]
+    clean: bool,
+    /// made up in place of an unloaded child whose parent recorded its light but none of its bodies, see `Cell::settle`
+    #[serde(skip)]
+    unsettled: bool,
+    /// not stored, cells are given the config of the tree they are loaded into, see `Cell::set_config`
+    #[serde(skip)]
+    config: TreeConfig,
 }
 
+// shared by trees of every body type, on the default so it can be named without one
 impl Cell {
+    // deepest any tree may be, limited by the sector ids. `TreeConfig::max_depth` may be shallower
     // 1ly ~= 2^53
     // pub const MAX_DEPTH: usize = 40; // 2^92m / 2^40 = 2^52m ~= 0.48ly
     pub const MAX_DEPTH: usize = 30; // 2^92m / 2^30 = 2^52m ~= 487ly
+}
 
-    /// inclusive min, exclusive max
-    pub fn new(bound_min: Vec3F, bound_max: Vec3F, luminosity: glam::DVec3) -> Self {
-        Self::with_depth(bound_min, bound_max, luminosity, 0, Sector::ID_ROOT)
+impl<B: Body> Cell<B> {
+    #[allow(unused)]
+    pub fn new(bounds: Aabb, luminosity: Spectrum) -> Self {
+        Self::with_depth(bounds, luminosity, 0, Sector::ID_ROOT, TreeConfig::DEFAULT)
     }
 
-    fn with_depth(bound_min: Vec3F, bound_max: Vec3F, luminosity: glam::DVec3, depth: usize, id: u128) -> Self {
-        assert!(bound_min.x <= bound_max.x && bound_min.y <= bound_max.y && bound_min.z <= bound_max.z, "invalid cell bounds {bound_min:?} {bound_max:?}");
-        
-        let centre = (bound_min + bound_max) / 2.0;
-        let half = centre - bound_min;
-        
+    fn with_depth(bounds: Aabb, luminosity: Spectrum, depth: usize, id: u128, config: TreeConfig) -> Self {
+        // split luminosity evenly between children, so that it is always the sum of its children's luminosity
         let children = Octant::ALL.map(|o| {
-            let min = Vec3F::from(o) * half + bound_min;
-            Node::Leaf(Leaf::with_depth(min, min + half, luminosity, depth + 1, Sector::id_push(id, o)))
+            Node::Leaf(Leaf::with_depth(bounds.octant(o as usize), luminosity / 8.0, depth + 1, Sector::id_push(id, o)))
         });
 
         Self {
-            sector: Sector::with_depth(id, bound_min, bound_max, luminosity, depth),
+            sector: Sector::with_depth(id, bounds, luminosity, depth),
             children,
+            visited: 0,
+            loaded: false,
+            clean: false,
+            unsettled: false,
+            config,
         }
     }
 
@@ -272,22 +705,139 @@ impl Cell {
         &self.sector
     }
 
+    #[allow(unused)]
+    pub fn config(&self) -> TreeConfig {
+        self.config
+    }
+
+    /// use `config` for this cell and everything loaded below it, e.g. after deserialising it.
+    /// cells already split differently keep their shape until bodies are added or removed
+    pub fn set_config(&mut self, config: TreeConfig) {
+        config.check();
+        self.config = config;
+        for child in &mut self.children {
+            if let Node::Cell(cell) = child {
+                cell.set_config(config);
+            }
+        }
+    }
+
+    /// a cell for the sector with the given id, with its luminosity split evenly between 8 unloaded children.
+    /// each child is asked of `load_cell` when it is first reached
+    pub fn with_unloaded_children(id: u128, bounds: Aabb, luminosity: Spectrum, config: TreeConfig) -> Self {
+        config.check();
+        let mut cell = Self::with_depth(bounds, luminosity, Sector::tree_coord(id).len(), id, config);
+        for (octant, child) in Octant::ALL.into_iter().zip(&mut cell.children) {
+            *child = Node::Unloaded(Sector::id_push(id, octant));
+        }
+        cell
+    }
+
+    // bulk builds only split subtrees with enough bodies to be worth a task on rayon's thread pool
+    const PARALLEL_MIN_BODIES: usize = 4096;
+    // visibility queries don't know how many bodies a subtree holds, so only the upper levels are split
+    const VISIBLE_PARALLEL_DEPTH: usize = 3;
+
+    /// build a cell from many bodies at once, produces the same tree as calling `add_body` with each body in order.
+    /// bodies are sorted by their octant path and large subtrees are built in parallel on rayon's thread pool, panics if any body not in bounds
+    pub fn from_bodies(bounds: Aabb, luminosity: Spectrum, bodies: Vec<B>, config: TreeConfig) -> Self {
+        Self::from_bodies_at(Sector::ID_ROOT, bounds, luminosity, bodies, config)
+    }
+
+    /// `from_bodies` for the sector with the given id, which must match the bounds
+    pub fn from_bodies_at(id: u128, bounds: Aabb, luminosity: Spectrum, bodies: Vec<B>, config: TreeConfig) -> Self {
+        config.check();
+
+        let depth = Sector::tree_coord(id).len();
+        let sector = Sector::with_depth(id, bounds, luminosity, depth);
+
+        let path_id = |b: &B| sector.path_id(b.position()).expect("point not in cell bounds");
+        // small cells, such as generated ones, aren't worth splitting into tasks
+        let path_ids = if bodies.len() >= Self::PARALLEL_MIN_BODIES {
+            bodies.par_iter().map(path_id).collect::<Vec<_>>()
+        } else {
+            bodies.iter().map(path_id).collect::<Vec<_>>()
+        };
+
+        let mut bodies = path_ids.into_iter().enumerate().zip(bodies).map(|((i, id), b)| (id, i, b)).collect::<Vec<_>>();
+        bodies.sort_unstable_by_key(|(id, i, _)| (*id, *i));
+
+        Self::build(bounds, luminosity, depth, id, bodies, config).0
+    }
+
+    /// build a cell from bodies sorted by path id and insertion index, returns the cell and the total luminosity of its bodies
+    fn build(bounds: Aabb, luminosity: Spectrum, depth: usize, id: u128, mut bodies: Vec<(u128, usize, B)>, config: TreeConfig) -> (Self, Spectrum) {
+        let parallel = bodies.len() >= Self::PARALLEL_MIN_BODIES;
+
+        let mut cell = Self::with_depth(bounds, luminosity, depth, id, config);
+
+        // split off from the back so each octant only moves its own bodies
+        let shift = 3 * (Cell::MAX_DEPTH - 1 - depth);
+        let mut octants: [Vec<(u128, usize, B)>; 8] = Default::default();
+        for octant in Octant::ALL.into_iter().rev() {
+            let start = bodies.partition_point(|(id, _, _)| (id >> shift) & 0b111 < octant as u128);
+            octants[octant as usize] = bodies.split_off(start);
+        }
+
+        let totals = if parallel {
+            cell.children.par_iter_mut().zip(octants).map(|(child, bodies)| Self::build_child(child, bodies, config)).collect::<Vec<_>>()
+        } else {
+            cell.children.iter_mut().zip(octants).map(|(child, bodies)| Self::build_child(child, bodies, config)).collect()
+        };
+
+        let total = totals.into_iter().sum();
+        cell.sector.luminosity += total;
+        for child in &cell.children {
+            let sector = match child {
+                Node::Cell(cell) => &cell.sector,
+                Node::Leaf(leaf) => &leaf.sector,
+                Node::Unloaded(_) => unreachable!("built cells are loaded"),
+            };
+            cell.sector.moments += sector.moments.shifted((sector.centre() - cell.sector.centre()).to_dvec3());
+        }
+
+        (cell, total)
+    }
+
+    /// fill a freshly created leaf, subdividing it if needed, returns the total luminosity of the bodies
+    fn build_child(child: &mut Node<B>, mut bodies: Vec<(u128, usize, B)>, config: TreeConfig) -> Spectrum {
+        let Node::Leaf(leaf) = child else { unreachable!("cell children start as leaves") };
+
+        if bodies.len() > config.leaf_capacity && leaf.sector.depth < config.max_depth {
+            let (cell, total) = Self::build(leaf.sector.bounds, leaf.sector.luminosity, leaf.sector.depth, leaf.sector.id, bodies, config);
+            *child = Node::Cell(Box::new(cell));
+            total
+        } else {
+            // leaves keep their bodies in insertion order, like `add_body`
+            bodies.sort_unstable_by_key(|(_, i, _)| *i);
+            let total = bodies.iter().map(|(_, _, b)| b.luminosity()).sum();
+            for (_, _, body) in &bodies {
+                leaf.sector.moments += Moments::of((body.position() - leaf.sector.centre()).to_dvec3(), body.luminosity().total());
+            }
+            leaf.sector.luminosity += total;
+            leaf.children.extend(bodies.into_iter().map(|(_, _, b)| b));
+            total
+        }
+    }
+
     /// add body to this cell, panics if body not in bounds
-    pub fn add_body(&mut self, body: Body) {
+    #[allow(unused)]
+    pub fn add_body(&mut self, body: B) {
         let pos = body.position();
 
         let octant = self.sector.octant(pos).expect("point not in cell bounds");
 
-        self.sector.luminosity += body.luminosity();
+        self.sector.add_light(&body);
+        self.clean = false;
 
         match &mut self.children[octant as usize] {
             Node::Cell(cell) => cell.add_body(body),
-            Node::Leaf(Leaf { sector, children }) => if children.len() > 0 && sector.depth < Self::MAX_DEPTH {
+            Node::Leaf(Leaf { sector, children }) => if children.len() >= self.config.leaf_capacity && sector.depth < self.config.max_depth {
                 self.subdivide(octant);
                 let Node::Cell(cell) = &mut self.children[octant as usize] else { unreachable!() };
                 cell.add_body(body);
             } else {
-                self.sector.luminosity += body.luminosity();
+                sector.add_light(&body);
                 children.push(body);
             }
             // Node::Unloaded(_) => {
@@ -299,150 +849,836 @@ impl Cell {
         }
     }
 
-    fn attenuation(dist: f64, radius: f64) -> f64 {
-        let r = dist / radius;
-        let att = 1.0 + r;
-        
-        att * att
-    }
+    /// remove a body at exactly `position` from this cell, returns `None` if there is none or it is in an unloaded cell.
+    /// cells left with at most one body collapse back into leaves
+    #[allow(unused)]
+    pub fn remove_body(&mut self, position: Vec3F) -> Option<B> {
+        let octant = self.sector.octant(position)?;
 
-    pub fn visible_from(&self, point: Vec3F, fovy: f32, screen_height: u32) -> bool {
-        // empty cell
-        if self.sector.luminosity.max_element() <= 0.0 {
-            return false;
-        }
+        let child = &mut self.children[octant as usize];
+        let body = match child {
+            Node::Cell(cell) => {
+                let body = cell.remove_body(position)?;
+                if let Some(leaf) = cell.collapse() {
+                    *child = Node::Leaf(leaf);
+                }
+                body
+            },
+            Node::Leaf(leaf) => {
+                let index = leaf.children.iter().position(|b| b.position() == position)?;
+                let body = leaf.children.remove(index);
+                leaf.sector.remove_light(&body);
+                body
+            },
+            Node::Unloaded(_) => return None,
+        };
 
-        let dist = Into::<glam::DVec3>::into(self.sector.centre - point).length() - self.sector.dimensions().max().to_num::<f64>();
-        // nearby or within cell
-        if dist <= 0.0 { return true; }
+        self.sector.remove_light(&body);
+        self.clean = false;
 
-        let s = self.sector.dimensions().max().to_num::<f64>() * fovy as f64 * screen_height as f64 / dist;
-        s > 0.5
+        Some(body)
     }
 
-    // this value is purposefully extremely small, we want the leaf nodes to show even if there is only the slightest chance they will be visible,
-    // especially given that point lights use additive blending, they may still be visible if overlapping
-    // const MIN_BRIGHTNESS: f64 = 0.01 / 255.0; // brightness below which not visible
-    const MESH_COMBINE_THRESHOLD: usize = 8192*16;
+    /// move every body in the loaded part of the tree along its velocity for `dt` seconds, re-binning bodies that leave their sector.
+    /// bodies in unloaded cells stay where they are, unloaded cells that bodies move into are loaded with `load_cell` as in `all_visible_from`.
+    /// bodies that would leave the tree entirely are dropped, returns the number of bodies moved
+    pub fn propagate<F: FnMut(u128, Aabb, Spectrum) -> Cell<B>>(&mut self, dt: f64, load_cell: &mut F) -> usize {
+        let mut moving = vec![];
+        self.drain_moving(&mut moving);
 
-    pub fn all_visible_from<F: Fn(u128, (Vec3F, Vec3F), glam::DVec3) -> Cell>(&mut self, point: Vec3F, fovy: f32, screen_height: u32, generate_cell: &mut F) -> Vec<CellVisibility> {
-        let mut points = vec![];
-        let mut visibility = vec![];
-        
-        // not visible, neither will children be visible
-        if !self.visible_from(point, fovy, screen_height) {
-            return visibility;
+        let mut moved = 0;
+        for mut body in moving {
+            let position = body.position() + Vec3F::from_dvec3(body.velocity() * dt);
+            if self.sector.octant(position).is_none() {
+                log::warn!("body at {:?} left the octree", body.position());
+                continue;
+            }
+            body.set_position(position);
+            self.add_body_loading(body, load_cell);
+            moved += 1;
         }
 
+        moved
+    }
+
+    /// load every unloaded cell below this one that `load` is true for with `load_cell`, as in `all_visible_from`,
+    /// along with the unloaded cells below those that it is true for
+    pub fn load_where<P: Fn(u128) -> bool, F: FnMut(u128, Aabb, Spectrum) -> Cell<B>>(&mut self, load: &P, load_cell: &mut F) {
         for octant in Octant::ALL {
-            let child = &mut self.children[octant as usize];
+            if matches!(self.children[octant as usize], Node::Unloaded(id) if load(id)) {
+                self.load_child(octant, load_cell);
+            }
+            if let Node::Cell(cell) = &mut self.children[octant as usize] {
+                cell.load_where(load, load_cell);
+            }
+        }
+    }
+
+    /// take every body with a velocity out of the loaded part of the tree, collapsing cells left with at most one body
+    fn drain_moving(&mut self, moving: &mut Vec<B>) {
+        let start = moving.len();
+
+        for child in &mut self.children {
             match child {
-                Node::Cell(child) => {
-                    let child_visibility = child.all_visible_from(point, fovy, screen_height, generate_cell);
-                    // combine small cells into larger ones
-                    if child_visibility.iter().map(|c| c.bodies.len()).sum::<usize>() < Self::MESH_COMBINE_THRESHOLD {
-                        points.extend(child_visibility.into_iter().map(|c| c.bodies).flatten());
-                    } else {
-                        visibility.extend(child_visibility);
+                Node::Cell(cell) => {
+                    cell.drain_moving(moving);
+                    if let Some(leaf) = cell.collapse() {
+                        *child = Node::Leaf(leaf);
                     }
                 },
                 Node::Leaf(leaf) => {
-                    // if the parent is visible, just assume all bodies are visible
-                    for child in &leaf.children {
-                        points.push(PointLight {
-                            position: child.position(),
-                            diameter: child.diameter(),
-                            colour: child.luminosity(),
-                            is_body: true,
-                        });
+                    // leaves without moving bodies keep their allocation, and so their estimated memory
+                    let drained = leaf.children.extract_if(.., |b| b.velocity() != glam::DVec3::ZERO).collect::<Vec<_>>();
+                    for body in &drained {
+                        leaf.sector.remove_light(body);
                     }
+                    moving.extend(drained);
                 },
-                Node::Unloaded(id) => {
-                    let cell = {
-                        let half = self.sector.half;
-                        let min = self.sector.centre - self.sector.half + Vec3F::from(octant) * half;
-                        let max = min + half;
-                        generate_cell(*id, (min, max), self.sector.luminosity / 8.0)
-                    };
-                    *child = Node::Cell(Box::new(cell));
-                },
+                Node::Unloaded(_) => {},
             }
         }
 
-        // some children are visible, return children
-        if points.len() > 0 || visibility.len() > 0 {
-            visibility.push(CellVisibility {
-                centre: self.sector.centre,
-                depth: self.sector.depth,
-                bodies: points,
-            });
-            
-            return visibility;
+        if moving.len() > start {
+            self.clean = false;
+        }
+        for body in &moving[start..] {
+            self.sector.remove_light(body);
         }
-        
-        // no children are visible, return point light approximation
-        
-        let diameter = self.sector.dimensions().max();
-        
-        visibility.push(CellVisibility {
-            centre: self.sector.centre,
-            depth: self.sector.depth,
-            bodies: vec![PointLight {
-                position: self.sector.centre,
-                diameter,
-                colour: self.sector.luminosity,
-                is_body: false,
-            }],
-        });
-        
-        visibility
     }
 
-    fn subdivide(&mut self, octant: Octant) {
-        if self.sector.depth >= Self::MAX_DEPTH { return; } // too deep, cannot subdivide
-
-        let Node::Leaf(leaf) = &mut self.children[octant as usize] else { return; }; // already subdivided
-        let bodies = leaf.children.drain(..).collect::<Vec<_>>();
-        
-        let half = self.sector.half;
-        let min = self.sector.centre - self.sector.half + Vec3F::from(octant) * half;
-        let max = min + half;
+    /// `add_body`, but loads unloaded cells in the way with `load_cell` rather than panicking
+    fn add_body_loading<F: FnMut(u128, Aabb, Spectrum) -> Cell<B>>(&mut self, body: B, load_cell: &mut F) {
+        let octant = self.sector.octant(body.position()).expect("point not in cell bounds");
+        self.load_child(octant, load_cell);
 
-        self.children[octant as usize] = Node::Cell(Box::new(Cell::with_depth(min, max, glam::DVec3::ZERO, self.sector.depth + 1, Sector::id_push(self.sector.id, octant))));
-        let Node::Cell(cell) = &mut self.children[octant as usize] else { unreachable!() };
+        if let Node::Cell(cell) = &mut self.children[octant as usize] {
+            self.sector.add_light(&body);
+            self.clean = false;
+            cell.add_body_loading(body, load_cell);
+        } else {
+            self.add_body(body);
+        }
+    }
 
-        for body in bodies {
-            cell.add_body(body);
+    /// move a body at exactly `from` to `to`, returns whether a body was moved.
+    /// nothing is removed if `to` is not in bounds
+    #[allow(unused)]
+    pub fn move_body(&mut self, from: Vec3F, to: Vec3F) -> bool {
+        if self.sector.octant(to).is_none() {
+            return false;
         }
+
+        let Some(mut body) = self.remove_body(from) else { return false; };
+        body.set_position(to);
+        self.add_body(body);
+
+        true
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// turn this cell into a leaf if its bodies fit in one, the inverse of `subdivide`
+    fn collapse(&mut self) -> Option<Leaf<B>> {
+        let mut count = 0;
+        for child in &self.children {
+            let Node::Leaf(leaf) = child else { return None; };
+            count += leaf.children.len();
+        }
+        if count > self.config.leaf_capacity {
+            return None;
+        }
 
-    #[test]
-    fn init() {
-        // check that we can insert without panicking
-        let mut cell = Cell::new(Vec3F::ZERO, Vec3F::ONE, glam::DVec3::ZERO);
-        cell.add_body(Body { position: Vec3F::ONE / 5.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE / 4.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE / 3.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE / 2.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE / 1.8, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE / 1.6, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE / 1.4, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE / 1.2, colour: glam::DVec3::ONE });
-
-        let mut cell = Cell::new(Vec3F::ONE, Vec3F::ONE * 2.0, glam::DVec3::ZERO);
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 5.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 4.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 3.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 2.0, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 1.8, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 1.6, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 1.4, colour: glam::DVec3::ONE });
-        cell.add_body(Body { position: Vec3F::ONE + Vec3F::ONE / 1.2, colour: glam::DVec3::ONE });
+        let children = self.children.iter_mut().flat_map(|child| {
+            let Node::Leaf(leaf) = child else { unreachable!() };
+            leaf.children.drain(..)
+        }).collect();
+
+        Some(Leaf {
+            sector: self.sector.clone(),
+            children,
+        })
+    }
+
+    fn attenuation(dist: f64, radius: f64) -> f64 {
+        let r = dist / radius;
+        let att = 1.0 + r;
+        
+        att * att
+    }
+
+    fn lod(&self, view: &View) -> Lod {
+        // empty cell
+        if self.sector.luminosity.max_element() <= 0.0 {
+            return Lod::Culled;
+        }
+
+        if !view.in_frustum(&self.sector) {
+            return Lod::Culled;
+        }
+
+        // brightest the cell could appear, if all of its light was at the nearest point
+        if view.flux(self.sector.luminosity, self.sector.bounds.distance_to(view.position).to_num()) < view.min_flux {
+            return Lod::Culled;
+        }
+
+        let dist = Into::<glam::DVec3>::into(self.sector.centre() - view.position).length() - self.sector.dimensions().max_element().to_num::<f64>();
+        // nearby or within cell
+        if dist <= 0.0 { return Lod::Resolve; }
+
+        let s = self.sector.dimensions().max_element().to_num::<f64>() * view.fovy as f64 * view.screen_height as f64 / dist;
+        if s > 0.5 { Lod::Resolve } else { Lod::Aggregate }
+    }
+
+    /// whole cell drawn as a single point light
+    fn aggregate(&self) -> CellVisibility {
+        CellVisibility {
+            id: self.sector.id,
+            centre: self.sector.centre(),
+            depth: self.sector.depth,
+            bodies: vec![PointLight {
+                position: self.sector.centroid(),
+                diameter: self.sector.spread(),
+                spectrum: self.sector.luminosity,
+                kind: None,
+                resolved: false,
+            }],
+        }
+    }
+
+    /// replace an unloaded child with the cell returned by `load_cell`
+    fn load_child<F: FnMut(u128, Aabb, Spectrum) -> Cell<B>>(&mut self, octant: Octant, load_cell: &mut F) {
+        Self::load_node(&self.sector, self.config, octant, &mut self.children[octant as usize], load_cell);
+    }
+
+    /// `load_child`, for when the parent's children are borrowed separately
+    fn load_node<F: FnMut(u128, Aabb, Spectrum) -> Cell<B>>(parent: &Sector, config: TreeConfig, octant: Octant, child: &mut Node<B>, load_cell: &mut F) {
+        if let Node::Unloaded(id) = child {
+            let mut cell = load_cell(*id, parent.child_bounds(octant), parent.luminosity / 8.0);
+            cell.set_config(config);
+            cell.set_clean();
+            cell.loaded = true;
+            *child = Node::Cell(Box::new(cell));
+        }
+    }
+
+    /// `load_cell` is called with the id, bounds and parent luminosity budget of any `Node::Unloaded` child that is reached,
+    /// the returned cell replaces it in the tree and is traversed immediately. every cell reached is marked as visited by query `visit`.
+    /// the upper levels of the tree are traversed in parallel, so `load_cell` may be called from several threads at once.
+    /// the cells below this one that could be evicted (see `Cell::evict`) are added to `candidates` with the query that last reached them,
+    /// these are the cells reached by the query, and the cells loaded below culled or folded cells
+    pub fn all_visible_from<F: Fn(u128, Aabb, Spectrum) -> Cell<B> + Sync>(&mut self, view: &View, visit: u64, load_cell: &F, candidates: &mut Vec<EvictionCandidate>) -> Vec<CellVisibility> {
+        self.visible_from(view, visit, load_cell, Self::VISIBLE_PARALLEL_DEPTH, candidates)
+    }
+
+    /// `all_visible_from`, children of cells shallower than `parallel_depth` are traversed as separate tasks on rayon's thread pool
+    fn visible_from<F: Fn(u128, Aabb, Spectrum) -> Cell<B> + Sync>(&mut self, view: &View, visit: u64, load_cell: &F, parallel_depth: usize, candidates: &mut Vec<EvictionCandidate>) -> Vec<CellVisibility> {
+        let mut points = vec![];
+        let mut visibility = vec![];
+
+        self.visited = visit;
+        
+        match self.lod(view) {
+            // not visible, neither will children be visible
+            Lod::Culled => {
+                self.unreached_candidates(candidates);
+                return visibility;
+            },
+            // too small to resolve, fold the subtree into its point light
+            Lod::Aggregate => {
+                self.unreached_candidates(candidates);
+                visibility.push(self.aggregate());
+                return visibility;
+            },
+            Lod::Resolve => {},
+        }
+
+        let (sector, config, children) = (&self.sector, self.config, &mut self.children);
+
+        let child_visibility = if sector.depth < parallel_depth {
+            let child_visibility = children.par_iter_mut().zip(Octant::ALL).map(|(child, octant)| {
+                let mut candidates = vec![];
+                let visible = Self::child_visible_from(sector, config, octant, child, view, visit, load_cell, parallel_depth, &mut candidates);
+                (visible, candidates)
+            }).collect::<Vec<_>>();
+
+            child_visibility.into_iter().map(|(visible, child_candidates)| {
+                candidates.extend(child_candidates);
+                visible
+            }).collect::<Vec<_>>()
+        } else {
+            children.iter_mut().zip(Octant::ALL).map(|(child, octant)| Self::child_visible_from(sector, config, octant, child, view, visit, load_cell, parallel_depth, candidates)).collect()
+        };
+
+        // merged in octant order, the same as if the children were traversed one after another
+        for (child_visibility, child_points) in child_visibility {
+            visibility.extend(child_visibility);
+            points.extend(child_points);
+        }
+
+        // some children are visible, return children
+        if points.len() > 0 || visibility.len() > 0 {
+            visibility.push(CellVisibility {
+                id: self.sector.id,
+                centre: self.sector.centre(),
+                depth: self.sector.depth,
+                bodies: points,
+            });
+            
+            return visibility;
+        }
+        
+        // no children are visible, but together they may be, return point light approximation
+        visibility.push(self.aggregate());
+        
+        visibility
+    }
+
+    /// visible cells of one child of `parent`, and the points it adds to the parent's cell
+    #[allow(clippy::too_many_arguments)]
+    fn child_visible_from<F: Fn(u128, Aabb, Spectrum) -> Cell<B> + Sync>(parent: &Sector, config: TreeConfig, octant: Octant, child: &mut Node<B>, view: &View, visit: u64, load_cell: &F, parallel_depth: usize, candidates: &mut Vec<EvictionCandidate>) -> (Vec<CellVisibility>, Vec<PointLight>) {
+        Self::load_node(parent, config, octant, child, &mut |id, bounds, luminosity| load_cell(id, bounds, luminosity));
+        match child {
+            Node::Cell(child) => {
+                let child_visibility = child.visible_from(view, visit, load_cell, parallel_depth, candidates);
+                if child.evictable() {
+                    candidates.push(EvictionCandidate { id: child.sector.id, depth: child.sector.depth, visited: visit });
+                }
+                // combine small cells into larger ones
+                if child_visibility.iter().map(|c| c.bodies.len()).sum::<usize>() < config.mesh_combine_threshold {
+                    (vec![], child_visibility.into_iter().flat_map(|c| c.bodies).collect())
+                } else {
+                    (child_visibility, vec![])
+                }
+            },
+            Node::Leaf(leaf) => {
+                if !view.in_frustum(&leaf.sector) {
+                    return (vec![], vec![]);
+                }
+                // if the parent is visible, just assume all bodies are visible unless too faint
+                let points = leaf.children.iter().filter_map(|child| {
+                    let dist = child.position().distance(view.position).to_num();
+                    if view.flux(child.luminosity(), dist) < view.min_flux {
+                        return None;
+                    }
+                    Some(PointLight {
+                        position: child.position(),
+                        diameter: child.diameter(),
+                        spectrum: child.luminosity(),
+                        kind: Some(child.kind()),
+                        resolved: view.pixel_size(child.diameter().to_num(), dist) >= PointLight::MIN_DISC_PIXELS,
+                    })
+                }).collect();
+                (vec![], points)
+            },
+            Node::Unloaded(_) => unreachable!("unloaded cell not replaced"),
+        }
+    }
+
+    // relative, luminosity sums are accumulated in different orders
+    const LUMINOSITY_TOLERANCE: f64 = 1e-9;
+
+    /// walk the tree and report every broken invariant, luminosity is not checked for cells with unloaded children
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        let depth = Sector::tree_coord(self.sector.id).len();
+        if depth != self.sector.depth {
+            violations.push(Violation::DepthMismatch { id: self.sector.id, depth: self.sector.depth, expected: depth });
+        }
+        self.validate_children(&mut violations);
+
+        violations
+    }
+
+    fn validate_children(&self, violations: &mut Vec<Violation>) {
+        let mut luminosity = Some(Spectrum::ZERO);
+        let mut moments = Some(Moments::default());
+
+        for octant in Octant::ALL {
+            let id = Sector::id_push(self.sector.id, octant);
+            let depth = self.sector.depth + 1;
+
+            let sector = match &self.children[octant as usize] {
+                Node::Cell(cell) => {
+                    cell.validate_children(violations);
+                    &cell.sector
+                },
+                Node::Leaf(leaf) => {
+                    for body in &leaf.children {
+                        if leaf.sector.octant(body.position()).is_none() {
+                            violations.push(Violation::OutOfBounds { id: leaf.sector.id, position: body.position() });
+                        }
+                    }
+                    if leaf.children.len() > self.config.leaf_capacity && leaf.sector.depth < self.config.max_depth {
+                        violations.push(Violation::Overfull { id: leaf.sector.id, bodies: leaf.children.len() });
+                    }
+                    let mut bodies = Moments::default();
+                    for body in &leaf.children {
+                        bodies += Moments::of((body.position() - leaf.sector.centre()).to_dvec3(), body.luminosity().total());
+                    }
+                    if !leaf.sector.moments.approx_eq(&bodies, leaf.sector.bounds.radius().to_num()) {
+                        violations.push(Violation::MomentsMismatch { id: leaf.sector.id });
+                    }
+                    &leaf.sector
+                },
+                Node::Unloaded(unloaded) => {
+                    if *unloaded != id {
+                        violations.push(Violation::IdMismatch { id: *unloaded, expected: id });
+                    }
+                    luminosity = None;
+                    moments = None;
+                    continue;
+                },
+            };
+
+            if Sector::tree_coord(sector.id) != Sector::tree_coord(id) {
+                violations.push(Violation::IdMismatch { id: sector.id, expected: id });
+            }
+            if sector.depth != depth {
+                violations.push(Violation::DepthMismatch { id: sector.id, depth: sector.depth, expected: depth });
+            }
+
+            if sector.bounds != self.sector.child_bounds(octant) {
+                violations.push(Violation::BoundsMismatch { id: sector.id });
+            }
+
+            luminosity = luminosity.map(|l| l + sector.luminosity);
+            if let Some(moments) = &mut moments {
+                *moments += sector.moments.shifted((sector.centre() - self.sector.centre()).to_dvec3());
+            }
+        }
+
+        if let Some(children) = luminosity {
+            let error = (self.sector.luminosity - children).abs().max_element();
+            if error > self.sector.luminosity.abs().max_element().max(children.abs().max_element()) * Self::LUMINOSITY_TOLERANCE {
+                violations.push(Violation::LuminosityMismatch { id: self.sector.id, luminosity: self.sector.luminosity, children });
+            }
+        }
+        if let Some(children) = moments {
+            if !self.sector.moments.approx_eq(&children, self.sector.bounds.radius().to_num()) {
+                violations.push(Violation::MomentsMismatch { id: self.sector.id });
+            }
+        }
+    }
+
+    /// shape and size of the loaded part of the tree
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            memory: std::mem::size_of::<Cell>(),
+            max_depth: self.config.max_depth,
+            ..Default::default()
+        };
+        self.collect_stats(&mut stats);
+        stats
+    }
+
+    fn collect_stats(&self, stats: &mut TreeStats) {
+        stats.count_at(self.sector.depth).cells += 1;
+        stats.cells += 1;
+
+        for child in &self.children {
+            match child {
+                Node::Cell(cell) => {
+                    stats.memory += std::mem::size_of::<Cell>();
+                    cell.collect_stats(stats);
+                },
+                Node::Leaf(leaf) => {
+                    let bodies = leaf.children.len();
+                    let depth = stats.count_at(leaf.sector.depth);
+                    depth.leaves += 1;
+                    depth.bodies += bodies;
+
+                    stats.leaves += 1;
+                    stats.bodies += bodies;
+                    *stats.leaf_occupancy.entry(bodies).or_default() += 1;
+                    if leaf.sector.depth == self.config.max_depth {
+                        stats.max_leaf_occupancy = stats.max_leaf_occupancy.max(bodies);
+                    }
+                    stats.memory += leaf.children.capacity() * std::mem::size_of::<B>();
+                },
+                Node::Unloaded(_) => {
+                    stats.count_at(self.sector.depth + 1).unloaded += 1;
+                    stats.unloaded += 1;
+                },
+            }
+        }
+    }
+
+    /// detach every subtree `stride` levels below this cell, replacing it with `Node::Unloaded`, and pass it to `page`.
+    /// detached subtrees are paged out recursively first, so each paged cell holds at most `stride` levels
+    pub fn page_out<E, F: FnMut(Cell<B>) -> Result<(), E>>(&mut self, stride: usize, page: &mut F) -> Result<(), E> {
+        assert!(stride > 0, "page stride must be at least 1");
+        self.page_out_at(stride, stride, page)
+    }
+
+    fn page_out_at<E, F: FnMut(Cell<B>) -> Result<(), E>>(&mut self, stride: usize, remaining: usize, page: &mut F) -> Result<(), E> {
+        for child in &mut self.children {
+            let Node::Cell(cell) = child else { continue };
+
+            if remaining > 1 {
+                cell.page_out_at(stride, remaining - 1, page)?;
+                continue;
+            }
+
+            let id = cell.sector.id;
+            let Node::Cell(mut cell) = std::mem::replace(child, Node::Unloaded(id)) else { unreachable!() };
+            cell.page_out_at(stride, stride, page)?;
+            page(*cell)?;
+        }
+
+        Ok(())
+    }
+
+    /// estimated memory used by this cell and everything loaded below it, in bytes
+    pub fn memory(&self) -> usize {
+        let mut memory = std::mem::size_of::<Cell>();
+
+        for child in &self.children {
+            match child {
+                Node::Cell(cell) => memory += cell.memory(),
+                Node::Leaf(leaf) => memory += leaf.children.capacity() * std::mem::size_of::<B>(),
+                Node::Unloaded(_) => {},
+            }
+        }
+
+        memory
+    }
+
+    /// loading this cell again would recreate it exactly
+    fn reloadable(&self) -> bool {
+        self.loaded && self.clean
+    }
+
+    /// cells inside an unmodified loaded cell have no page of their own, so are only evicted along with it
+    fn evictable(&self) -> bool {
+        self.reloadable() || !self.clean
+    }
+
+    /// mark a cell that was made up rather than loaded, such as a generated one. its bodies are added to its ancestors' moments by `Cell::settle`
+    pub fn set_unsettled(&mut self) {
+        self.unsettled = true;
+    }
+
+    /// add the bodies of cells marked by `set_unsettled` since the last call to the moments of every cell above them.
+    /// the cells changed are no longer clean, so are paged out rather than made up again with their bodies counted twice
+    pub fn settle(&mut self) {
+        self.settle_children();
+    }
+
+    /// `settle`, returns the moments added to this cell
+    fn settle_children(&mut self) -> Moments {
+        let mut added = Moments::default();
+        for child in &mut self.children {
+            if let Node::Cell(cell) = child {
+                let mut moments = cell.settle_children();
+                if cell.unsettled {
+                    // its own moments now include everything below it
+                    moments = cell.sector.moments;
+                    cell.unsettled = false;
+                    cell.clean = false;
+                }
+                if moments.weight > 0.0 {
+                    added += moments.shifted((cell.sector.centre() - self.sector.centre()).to_dvec3());
+                }
+            }
+        }
+
+        if added.weight > 0.0 {
+            self.sector.moments += added;
+            self.clean = false;
+        }
+        added
+    }
+
+    /// mark this cell and everything loaded below it as unmodified
+    fn set_clean(&mut self) {
+        self.clean = true;
+        for child in &mut self.children {
+            if let Node::Cell(cell) = child {
+                cell.set_clean();
+            }
+        }
+    }
+
+    /// loaded children not reached by the current query, with the query that last reached them
+    fn unreached_candidates(&self, candidates: &mut Vec<EvictionCandidate>) {
+        for child in &self.children {
+            if let Node::Cell(cell) = child {
+                if cell.evictable() {
+                    candidates.push(EvictionCandidate { id: cell.sector.id, depth: cell.sector.depth, visited: cell.visited });
+                }
+            }
+        }
+    }
+
+    /// unload everything below this cell that would be loaded again unchanged
+    fn unload_reloadable(&mut self) {
+        for child in &mut self.children {
+            let Node::Cell(cell) = child else { continue; };
+            if cell.reloadable() {
+                *child = Node::Unloaded(cell.sector.id);
+            } else if !cell.clean {
+                cell.unload_reloadable();
+            }
+        }
+    }
+
+    /// replace the loaded cell with the given id below this one with `Node::Unloaded`, so it is loaded again when next reached.
+    /// cells `load_cell` wouldn't recreate as they are, such as those built from catalogues or modified since loading, are passed to `page` first,
+    /// without the unmodified cells loaded below them. cells inside an unmodified loaded cell are only evicted along with it.
+    /// returns the estimated memory freed, or `None` if the cell isn't loaded or can't be evicted on its own
+    pub fn evict<E, F: FnMut(&Cell<B>) -> Result<(), E>>(&mut self, id: u128, page: &mut F) -> Result<Option<usize>, E> {
+        let path = Sector::tree_coord(id);
+        if path.len() <= self.sector.depth || Sector::tree_coord(self.sector.id)[..] != path[..self.sector.depth] {
+            return Ok(None);
+        }
+        self.evict_path(&path[self.sector.depth..], page)
+    }
+
+    fn evict_path<E, F: FnMut(&Cell<B>) -> Result<(), E>>(&mut self, path: &[Octant], page: &mut F) -> Result<Option<usize>, E> {
+        let Some((octant, rest)) = path.split_first() else { return Ok(None); };
+        let child = &mut self.children[*octant as usize];
+        let Node::Cell(cell) = child else { return Ok(None); };
+
+        if !rest.is_empty() {
+            return cell.evict_path(rest, page);
+        }
+
+        if !cell.evictable() {
+            return Ok(None);
+        }
+        let memory = cell.memory();
+        if !cell.reloadable() {
+            cell.unload_reloadable();
+            page(cell)?;
+        }
+        *child = Node::Unloaded(cell.sector.id);
+
+        Ok(Some(memory))
+    }
+
+    fn subdivide(&mut self, octant: Octant) {
+        if self.sector.depth >= self.config.max_depth { return; } // too deep, cannot subdivide
+
+        let Node::Leaf(leaf) = &mut self.children[octant as usize] else { return; }; // already subdivided
+        let bodies = leaf.children.drain(..).collect::<Vec<_>>();
+        // keep whatever luminosity the leaf had besides its bodies
+        let luminosity = bodies.iter().fold(leaf.sector.luminosity, |l, b| l - b.luminosity());
+        
+        let bounds = self.sector.child_bounds(octant);
+        self.children[octant as usize] = Node::Cell(Box::new(Cell::with_depth(bounds, luminosity, self.sector.depth + 1, Sector::id_push(self.sector.id, octant), self.config)));
+        let Node::Cell(cell) = &mut self.children[octant as usize] else { unreachable!() };
+
+        for body in bodies {
+            cell.add_body(body);
+        }
+    }
+}
+
+/// body found by a spatial query, ordered by distance
+struct Neighbour<'a, B> {
+    distance: FP128,
+    body: &'a B,
+}
+
+impl<B> PartialEq for Neighbour<'_, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance == other.distance
+    }
+}
+
+impl<B> Eq for Neighbour<'_, B> {}
+
+impl<B> PartialOrd for Neighbour<'_, B> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<B> Ord for Neighbour<'_, B> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.cmp(&other.distance)
+    }
+}
+
+/// body hit by `Cell::pick`
+#[derive(Debug, Clone)]
+#[allow(unused)]
+pub struct Pick<B = Star> {
+    pub body: B,
+    pub distance: FP128,
+    /// luminosity over distance squared
+    pub brightness: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(unused)]
+pub enum PickOrder {
+    /// brightest first
+    Brightness,
+    /// nearest first
+    Distance,
+}
+
+/// spatial queries, other than `pick` these only search loaded parts of the tree
+#[allow(unused)]
+impl<B: Body> Cell<B> {
+    /// all bodies within `tolerance` radians of the ray from `origin` in `direction`, allowing for their diameter.
+    /// only descends into sectors the cone intersects, unloaded children are loaded as in `all_visible_from`
+    pub fn pick<F: FnMut(u128, Aabb, Spectrum) -> Cell<B>>(&mut self, origin: Vec3F, direction: glam::DVec3, tolerance: f64, order: PickOrder, load_cell: &mut F) -> Vec<Pick<B>> {
+        let mut picks = vec![];
+        self.pick_into(origin, direction.normalize(), tolerance, load_cell, &mut picks);
+
+        match order {
+            PickOrder::Brightness => picks.sort_by(|a, b| b.brightness.total_cmp(&a.brightness)),
+            PickOrder::Distance => picks.sort_by_key(|p| p.distance),
+        }
+
+        picks
+    }
+
+    fn pick_into<F: FnMut(u128, Aabb, Spectrum) -> Cell<B>>(&mut self, origin: Vec3F, direction: glam::DVec3, tolerance: f64, load_cell: &mut F, picks: &mut Vec<Pick<B>>) {
+        for octant in Octant::ALL {
+            if !self.sector.child_bounds(octant).in_cone(origin, direction, tolerance) {
+                continue;
+            }
+
+            self.load_child(octant, load_cell);
+            match &mut self.children[octant as usize] {
+                Node::Cell(cell) => cell.pick_into(origin, direction, tolerance, load_cell, picks),
+                Node::Leaf(leaf) => for body in &leaf.children {
+                    let to_body = (body.position() - origin).to_dvec3();
+                    let dist = to_body.length();
+                    let radius = body.diameter().to_num::<f64>() / 2.0;
+
+                    let hit = dist <= radius || to_body.angle_between(direction) - (radius / dist).asin() <= tolerance;
+                    if hit {
+                        picks.push(Pick {
+                            body: body.clone(),
+                            distance: body.position().distance(origin),
+                            brightness: body.luminosity().total() / (dist * dist).max(f64::MIN_POSITIVE),
+                        });
+                    }
+                },
+                Node::Unloaded(_) => unreachable!(),
+            }
+        }
+    }
+
+    /// the `k` bodies closest to `point`, nearest first
+    pub fn nearest(&self, point: Vec3F, k: usize) -> Vec<(B, FP128)> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.nearest_into(point, k, &mut heap);
+        }
+
+        heap.into_sorted_vec().into_iter().map(|n| (n.body.clone(), n.distance)).collect()
+    }
+
+    fn nearest_into<'a>(&'a self, point: Vec3F, k: usize, heap: &mut BinaryHeap<Neighbour<'a, B>>) {
+        // closest sectors first, so the furthest neighbour found shrinks as fast as possible
+        let mut children = self.children.iter().filter_map(|child| match child {
+            Node::Cell(cell) => Some((cell.sector.bounds.distance_to(point), child)),
+            Node::Leaf(leaf) if !leaf.children.is_empty() => Some((leaf.sector.bounds.distance_to(point), child)),
+            Node::Leaf(_) | Node::Unloaded(_) => None,
+        }).collect::<Vec<_>>();
+        children.sort_by_key(|(d, _)| *d);
+
+        for (sector_distance, child) in children {
+            if heap.len() == k && heap.peek().is_some_and(|n| sector_distance > n.distance) {
+                break;
+            }
+
+            match child {
+                Node::Cell(cell) => cell.nearest_into(point, k, heap),
+                Node::Leaf(leaf) => for body in &leaf.children {
+                    let distance = body.position().distance(point);
+                    if heap.len() < k {
+                        heap.push(Neighbour { distance, body });
+                    } else if heap.peek().is_some_and(|n| distance < n.distance) {
+                        heap.pop();
+                        heap.push(Neighbour { distance, body });
+                    }
+                },
+                Node::Unloaded(_) => unreachable!(),
+            }
+        }
+    }
+
+    /// all bodies within `radius` of `point` (inclusive), nearest first
+    pub fn within_radius(&self, point: Vec3F, radius: FP128) -> Vec<(B, FP128)> {
+        let mut found = vec![];
+        self.within_radius_into(point, radius, &mut found);
+        found.sort();
+
+        found.into_iter().map(|n| (n.body.clone(), n.distance)).collect()
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vec3F, radius: FP128, found: &mut Vec<Neighbour<'a, B>>) {
+        for child in &self.children {
+            match child {
+                Node::Cell(cell) => if cell.sector.bounds.intersects_sphere(point, radius) {
+                    cell.within_radius_into(point, radius, found);
+                },
+                Node::Leaf(leaf) => if leaf.sector.bounds.intersects_sphere(point, radius) {
+                    for body in &leaf.children {
+                        let distance = body.position().distance(point);
+                        if distance <= radius {
+                            found.push(Neighbour { distance, body });
+                        }
+                    }
+                },
+                Node::Unloaded(_) => {},
+            }
+        }
+    }
+
+    /// all bodies inside the box
+    pub fn within_box(&self, bounds: &Aabb) -> Vec<B> {
+        let mut found = vec![];
+        self.within_box_into(bounds, &mut found);
+        found
+    }
+
+    fn within_box_into(&self, bounds: &Aabb, found: &mut Vec<B>) {
+        for child in &self.children {
+            match child {
+                Node::Cell(cell) => if cell.sector.bounds.intersects(bounds) {
+                    cell.within_box_into(bounds, found);
+                },
+                Node::Leaf(leaf) => if leaf.sector.bounds.intersects(bounds) {
+                    found.extend(leaf.children.iter().filter(|b| bounds.contains(b.position())).cloned());
+                },
+                Node::Unloaded(_) => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init() {
+        // check that we can insert without panicking
+        let mut cell = Cell::new(Aabb::new(Vec3F::ZERO, Vec3F::ONE), Spectrum::ZERO);
+        cell.add_body(Star::new(Vec3F::ONE / 5.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE / 4.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE / 3.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE / 2.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE / 1.8, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE / 1.6, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE / 1.4, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE / 1.2, 0.5.to_fixed(), Spectrum::ONE));
+
+        let mut cell = Cell::new(Aabb::new(Vec3F::ONE, Vec3F::ONE * 2.0), Spectrum::ZERO);
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 5.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 4.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 3.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 2.0, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 1.8, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 1.6, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 1.4, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::ONE + Vec3F::ONE / 1.2, 0.5.to_fixed(), Spectrum::ONE));
     }
 
     #[test]
@@ -460,4 +1696,554 @@ mod tests {
             assert_eq!(Sector::tree_coord(id), octs);
         }
     }
+
+    #[test]
+    fn from_bodies() {
+        let mut bodies = random_stars(0, Cell::<Star>::PARALLEL_MIN_BODIES * 2, 512.0);
+        // bodies sharing a leaf at max depth
+        bodies.extend((0..8).map(|i| Star::new(Vec3F::ONE * 3.0, 0.5.to_fixed(), Spectrum::splat(i as f64))));
+
+        let (min, max) = (Vec3F::ONE * -512.0, Vec3F::ONE * 512.0);
+        let mut incremental = Cell::new(Aabb::new(min, max), Spectrum::ONE);
+        for body in bodies.iter().cloned() {
+            incremental.add_body(body);
+        }
+        let mut bulk = Cell::from_bodies(Aabb::new(min, max), Spectrum::ONE, bodies, TreeConfig::DEFAULT);
+
+        assert_eq!(incremental.validate(), vec![]);
+        assert_eq!(bulk.validate(), vec![]);
+        clear_moments(&mut incremental);
+        clear_moments(&mut bulk);
+        assert_eq!(bincode::serialize(&incremental).unwrap(), bincode::serialize(&bulk).unwrap());
+    }
+
+    #[test]
+    fn tree_config() {
+        let config = TreeConfig { leaf_capacity: 8, max_depth: 4, ..TreeConfig::DEFAULT };
+
+        let bodies = random_stars(7, 1024, 64.0);
+
+        let (min, max) = (Vec3F::ONE * -64.0, Vec3F::ONE * 64.0);
+        let mut incremental = Cell::new(Aabb::new(min, max), Spectrum::ZERO);
+        incremental.set_config(config);
+        for body in bodies.iter().cloned() {
+            incremental.add_body(body);
+        }
+        let mut bulk = Cell::from_bodies(Aabb::new(min, max), Spectrum::ZERO, bodies.clone(), config);
+
+        assert_eq!(incremental.validate(), vec![]);
+        assert_eq!(bulk.validate(), vec![]);
+
+        let stats = bulk.stats();
+        assert_eq!(stats.max_depth, 4);
+        assert!(stats.depths.len() <= 5);
+        assert!(stats.leaf_occupancy.keys().any(|&n| n > 1));
+
+        clear_moments(&mut incremental);
+        clear_moments(&mut bulk);
+        assert_eq!(bincode::serialize(&incremental).unwrap(), bincode::serialize(&bulk).unwrap());
+
+        // a shallower tree than the default, and the default validator finds it overfull
+        let default = Cell::from_bodies(Aabb::new(min, max), Spectrum::ZERO, bodies.clone(), TreeConfig::DEFAULT);
+        assert!(default.stats().cells > bulk.stats().cells);
+        let saved = bincode::serialize(&Cell::from_bodies(Aabb::new(min, max), Spectrum::ZERO, bodies.clone(), config)).unwrap();
+        let mut reloaded = bincode::deserialize::<Cell>(&saved).unwrap();
+        assert!(!reloaded.validate().is_empty());
+        reloaded.set_config(config);
+        assert_eq!(reloaded.validate(), vec![]);
+
+        // removing collapses cells back into leaves of up to `leaf_capacity` bodies
+        for body in &bodies[..1000] {
+            reloaded.remove_body(body.position).unwrap();
+        }
+        assert_eq!(reloaded.validate(), vec![]);
+        assert_eq!(reloaded.stats().cells, Cell::from_bodies(Aabb::new(min, max), Spectrum::ZERO, bodies[1000..].to_vec(), config).stats().cells);
+    }
+
+    /// moments are summed in a different order depending on how a tree was built, they are checked by `validate` instead
+    fn clear_moments(cell: &mut Cell) {
+        cell.sector.moments = Moments::default();
+        for child in &mut cell.children {
+            match child {
+                Node::Cell(cell) => clear_moments(cell),
+                Node::Leaf(leaf) => leaf.sector.moments = Moments::default(),
+                Node::Unloaded(_) => {},
+            }
+        }
+    }
+
+    #[test]
+    fn remove_body() {
+        let mut bodies = random_stars(1, 512, 64.0);
+        bodies.extend((0..4).map(|_| Star::new(Vec3F::ONE * 3.0, 0.5.to_fixed(), Spectrum::ONE)));
+
+        let (min, max) = (Vec3F::ONE * -64.0, Vec3F::ONE * 64.0);
+        let mut cell = Cell::from_bodies(Aabb::new(min, max), Spectrum::ZERO, bodies.clone(), TreeConfig::DEFAULT);
+
+        assert!(cell.remove_body(Vec3F::ONE * 100.0).is_none());
+        assert!(cell.remove_body(Vec3F::ONE * 4.0).is_none());
+
+        // removing leaves the same tree as never having added
+        let mut remaining = vec![];
+        for (i, body) in bodies.into_iter().enumerate() {
+            if i % 3 == 0 {
+                remaining.push(body);
+            } else {
+                let removed = cell.remove_body(body.position).unwrap();
+                assert_eq!(removed.spectrum, body.spectrum);
+                assert_eq!(cell.validate(), vec![]);
+            }
+        }
+        let mut expected = Cell::from_bodies(Aabb::new(min, max), Spectrum::ZERO, remaining.clone(), TreeConfig::DEFAULT);
+        let mut cleared = bincode::deserialize::<Cell>(&bincode::serialize(&cell).unwrap()).unwrap();
+        clear_moments(&mut expected);
+        clear_moments(&mut cleared);
+        assert_eq!(bincode::serialize(&expected).unwrap(), bincode::serialize(&cleared).unwrap());
+
+        for body in &remaining {
+            cell.remove_body(body.position).unwrap();
+        }
+        assert!(cell.children.iter().all(|c| matches!(c, Node::Leaf(leaf) if leaf.children.is_empty())));
+        assert_eq!(cell.sector.luminosity, Spectrum::ZERO);
+    }
+
+    #[test]
+    fn move_body() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ZERO, Vec3F::ONE * 64.0), Spectrum::ONE);
+        for i in 1..32 {
+            let f = i as f64;
+            cell.add_body(Star::new(Vec3F::from_f64s(f, (f * 7.0) % 64.0, (f * 13.0) % 64.0), 0.5.to_fixed(), Spectrum::splat(f)));
+        }
+        assert_eq!(cell.validate(), vec![]);
+
+        assert!(!cell.move_body(Vec3F::from_f64s(1.0, 7.0, 13.0), Vec3F::ONE * 65.0));
+        assert!(!cell.move_body(Vec3F::ONE * 0.5, Vec3F::ONE));
+
+        for i in 1..32 {
+            let f = i as f64;
+            assert!(cell.move_body(Vec3F::from_f64s(f, (f * 7.0) % 64.0, (f * 13.0) % 64.0), Vec3F::from_f64s(63.0 - f, f, 0.5)));
+            assert_eq!(cell.validate(), vec![]);
+        }
+        for i in 1..32 {
+            let f = i as f64;
+            assert_eq!(cell.remove_body(Vec3F::from_f64s(63.0 - f, f, 0.5)).unwrap().spectrum, Spectrum::splat(f));
+        }
+        assert_eq!(cell.validate(), vec![]);
+    }
+
+    #[test]
+    fn propagate() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ZERO, Vec3F::ONE * 64.0), Spectrum::ZERO);
+        for i in 1..16 {
+            let f = i as f64;
+            cell.add_body(Star::new(Vec3F::from_f64s(f, f, 1.0), 0.5.to_fixed(), Spectrum::ONE));
+        }
+        let still = cell.within_box(&Aabb::new(Vec3F::ZERO, Vec3F::ONE * 64.0)).len();
+        // crosses into another sector, leaves the tree, and moves into the unloaded far corner
+        cell.add_body(Star { position: Vec3F::from_f64s(2.0, 30.0, 1.0), radius: 0.5.to_fixed(), velocity: glam::dvec3(0.0, 1.0, 0.0), spectrum: Spectrum::ONE });
+        cell.add_body(Star { position: Vec3F::from_f64s(3.0, 30.0, 1.0), radius: 0.5.to_fixed(), velocity: glam::dvec3(-1.0, 0.0, 0.0), spectrum: Spectrum::ONE });
+        cell.add_body(Star { position: Vec3F::from_f64s(20.0, 20.0, 20.0), radius: 0.5.to_fixed(), velocity: glam::dvec3(1.0, 1.0, 1.0), spectrum: Spectrum::ONE });
+        cell.children[Octant::ALL.len() - 1] = Node::Unloaded(Sector::id_push(Sector::ID_ROOT, Octant::ALL[7]));
+        assert_eq!(cell.validate(), vec![]);
+
+        let mut loaded = vec![];
+        // the far corner held no light before it was unloaded
+        let moved = cell.propagate(16.0, &mut |id, bounds, _| {
+            loaded.push(id);
+            Cell::from_bodies_at(id, bounds, Spectrum::ZERO, vec![], TreeConfig::DEFAULT)
+        });
+        assert_eq!(moved, 2);
+        assert_eq!(loaded, vec![Sector::id_push(Sector::ID_ROOT, Octant::ALL[7])]);
+        assert_eq!(cell.validate(), vec![]);
+
+        let bodies = cell.within_box(&Aabb::new(Vec3F::ZERO, Vec3F::ONE * 64.0));
+        assert_eq!(bodies.len(), still + 2);
+        assert!(bodies.iter().any(|b| b.position == Vec3F::from_f64s(2.0, 46.0, 1.0)));
+        assert!(bodies.iter().any(|b| b.position == Vec3F::ONE * 36.0));
+        assert_eq!(bodies.iter().filter(|b| b.velocity == glam::DVec3::ZERO).count(), still);
+    }
+
+    #[test]
+    fn validate() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ZERO, Vec3F::ONE * 64.0), Spectrum::ONE);
+        for i in 1..32 {
+            let f = i as f64;
+            cell.add_body(Star::new(Vec3F::from_f64s(f, (f * 7.0) % 64.0, (f * 13.0) % 64.0), 0.5.to_fixed(), Spectrum::splat(f)));
+        }
+        assert_eq!(cell.validate(), vec![]);
+
+        let Node::Cell(child) = &mut cell.children[0] else { panic!("expected subdivided cell") };
+        child.sector.luminosity += Spectrum::ONE;
+        let (child_id, child_luminosity) = (child.sector.id, child.sector.luminosity);
+        assert_eq!(cell.validate(), vec![
+            Violation::LuminosityMismatch { id: child_id, luminosity: child_luminosity, children: child_luminosity - Spectrum::ONE },
+            Violation::LuminosityMismatch { id: Sector::ID_ROOT, luminosity: cell.sector.luminosity, children: cell.sector.luminosity + Spectrum::ONE },
+        ]);
+        let Node::Cell(child) = &mut cell.children[0] else { unreachable!() };
+        child.sector.luminosity -= Spectrum::ONE;
+
+        let Node::Leaf(leaf) = &mut cell.children[7] else { panic!("expected leaf") };
+        let expected = leaf.sector.id;
+        let id = Sector::id_push(expected, Octant::NxNyNz);
+        leaf.children.push(Star::new(Vec3F::ONE * 48.0, 0.5.to_fixed(), Spectrum::ZERO));
+        leaf.children.push(Star::new(Vec3F::ZERO, 0.5.to_fixed(), Spectrum::ZERO));
+        leaf.sector.id = id;
+        leaf.sector.depth += 1;
+        leaf.sector.bounds = Aabb::from_centre_size(leaf.sector.centre(), leaf.sector.dimensions() / 2.0);
+        assert_eq!(cell.validate(), vec![
+            Violation::OutOfBounds { id, position: Vec3F::ZERO },
+            Violation::Overfull { id, bodies: 2 },
+            Violation::IdMismatch { id, expected },
+            Violation::DepthMismatch { id, depth: 2, expected: 1 },
+            Violation::BoundsMismatch { id },
+        ]);
+    }
+
+    #[test]
+    fn queries() {
+        // spread over the whole region, so squared distances would overflow
+        let size = crate::universe::Universe::REGION_SIZE.to_num::<f64>() / 2.0;
+        let bodies = random_stars(2, 2048, size);
+        let cell = Cell::from_bodies(Aabb::new(Vec3F::ONE * -size, Vec3F::ONE * size), Spectrum::ZERO, bodies.clone(), TreeConfig::DEFAULT);
+
+        let point = Vec3F::from_f64s(size / 3.0, -size / 5.0, size / 7.0);
+        let mut expected = bodies.iter().map(|b| (b.position, b.position.distance(point))).collect::<Vec<_>>();
+        expected.sort_by_key(|(_, d)| *d);
+
+        let nearest = cell.nearest(point, 20);
+        assert_eq!(nearest.iter().map(|(b, d)| (b.position, *d)).collect::<Vec<_>>(), expected[..20]);
+        assert_eq!(cell.nearest(point, 0).len(), 0);
+        assert_eq!(cell.nearest(point, 4096).len(), 2048);
+
+        let radius = expected[100].1;
+        let within = cell.within_radius(point, radius);
+        assert_eq!(within.iter().map(|(b, d)| (b.position, *d)).collect::<Vec<_>>(), expected[..=100]);
+
+        let (min, max) = (Vec3F::ONE * -size / 2.0, Vec3F::from_f64s(0.0, size, size / 4.0));
+        let mut within = cell.within_box(&Aabb::new(min, max)).into_iter().map(|b| b.position.to_f64s()).collect::<Vec<_>>();
+        let mut expected = bodies.iter().map(|b| b.position).filter(|p| {
+            min.x <= p.x && min.y <= p.y && min.z <= p.z && p.x < max.x && p.y < max.y && p.z < max.z
+        }).map(|p| p.to_f64s()).collect::<Vec<_>>();
+        within.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(!expected.is_empty());
+        assert_eq!(within, expected);
+
+        // compare with f64 distance
+        let d = (bodies[0].position.to_dvec3() - point.to_dvec3()).length();
+        assert!((bodies[0].position.distance(point).to_num::<f64>() - d).abs() / d < 1e-9);
+    }
+
+    #[test]
+    fn pick() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO);
+        // along -z from the origin, the closest is the faintest
+        for i in 1..8 {
+            let f = i as f64;
+            cell.add_body(Star::new(Vec3F::from_f64s(0.0, 0.0, -f * 8.0), 0.5.to_fixed(), Spectrum::splat(f * f * f)));
+        }
+        // just outside the cone
+        cell.add_body(Star::new(Vec3F::from_f64s(8.0, 0.0, -8.0), 0.5.to_fixed(), Spectrum::ONE));
+        // behind
+        cell.add_body(Star::new(Vec3F::from_f64s(0.0, 0.0, 8.0), 0.5.to_fixed(), Spectrum::ONE));
+
+        let no_load = &mut |_, _, _| -> Cell { unreachable!("no unloaded cells") };
+
+        let picks = cell.pick(Vec3F::ZERO, glam::DVec3::NEG_Z, 0.1, PickOrder::Distance, no_load);
+        assert_eq!(picks.iter().map(|p| p.distance.to_num::<f64>()).collect::<Vec<_>>(), vec![8.0, 16.0, 24.0, 32.0, 40.0, 48.0, 56.0]);
+
+        let picks = cell.pick(Vec3F::ZERO, glam::DVec3::NEG_Z, 0.1, PickOrder::Brightness, no_load);
+        assert_eq!(picks.iter().map(|p| p.distance.to_num::<f64>()).collect::<Vec<_>>(), vec![56.0, 48.0, 40.0, 32.0, 24.0, 16.0, 8.0]);
+
+        let picks = cell.pick(Vec3F::ZERO, glam::dvec3(1.0, 0.0, -1.0), 0.1, PickOrder::Distance, no_load);
+        assert_eq!(picks.len(), 1);
+        assert_eq!(picks[0].body.position, Vec3F::from_f64s(8.0, 0.0, -8.0));
+    }
+
+    #[test]
+    fn frustum() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO);
+        let ahead = Vec3F::from_f64s(0.5, 0.5, -40.0);
+        let behind = Vec3F::from_f64s(0.5, 0.5, 40.0);
+        let right = Vec3F::from_f64s(40.0, 0.5, -0.5);
+        // pairs of nearby bodies, so each ends up in a small leaf away from the camera
+        for position in [ahead, behind, right] {
+            cell.add_body(Star::new(position, 0.5.to_fixed(), Spectrum::ONE));
+            cell.add_body(Star::new(position + Vec3F::from_f64s(0.1, 0.0, 0.0), 0.5.to_fixed(), Spectrum::ONE));
+        }
+
+        let visible_bodies = |cell: &mut Cell, view: &View| {
+            let mut bodies = cell.all_visible_from(view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut vec![])
+                .into_iter().flat_map(|c| c.bodies).filter(|b| b.kind.is_some() && [ahead, behind, right].contains(&b.position)).map(|b| b.position.to_f64s()).collect::<Vec<_>>();
+            bodies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            bodies
+        };
+
+        let mut view = View { position: Vec3F::from_f64s(0.5, 0.5, 0.5), rotation: glam::Quat::IDENTITY, fovy: 1.0, aspect: 1.0, screen_height: 1080, min_flux: View::DEFAULT_MIN_FLUX };
+        assert_eq!(visible_bodies(&mut cell, &view), vec![ahead.to_f64s()]);
+
+        // looking right, the view matrix rotates the world the opposite way
+        view.rotation = glam::Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        assert_eq!(visible_bodies(&mut cell, &view), vec![right.to_f64s()]);
+
+        // wide enough to see both
+        view.rotation = glam::Quat::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        view.fovy = 1.0;
+        view.aspect = 2.5;
+        assert_eq!(visible_bodies(&mut cell, &view), vec![ahead.to_f64s(), right.to_f64s()]);
+    }
+
+    #[test]
+    fn moments() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ONE);
+        cell.add_body(Star::new(Vec3F::from_f64s(10.0, 10.0, 10.0), 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(Vec3F::from_f64s(20.0, 10.0, 10.0), 0.5.to_fixed(), Spectrum::ONE * 3.0));
+
+        let centroid = Vec3F::from_f64s(17.5, 10.0, 10.0);
+        // rms of 7.5 and 2.5 weighted 1:3
+        let spread = (18.75f64).sqrt() * 2.0;
+
+        // every level agrees, so switching between them doesn't move the light
+        let Node::Cell(child) = &cell.children[Octant::PxPyPz as usize] else { panic!("not subdivided") };
+        for sector in [&cell.sector, &child.sector] {
+            assert!(sector.centroid().distance(centroid) < 1e-6);
+            assert!((sector.spread().to_num::<f64>() - spread).abs() < 1e-6);
+        }
+
+        cell.remove_body(Vec3F::from_f64s(20.0, 10.0, 10.0)).unwrap();
+        assert!(cell.sector.centroid().distance(Vec3F::ONE * 10.0) < 1e-6);
+        assert!(cell.sector.spread() < 1e-6);
+
+        // luminosity without any bodies has no position
+        cell.remove_body(Vec3F::ONE * 10.0).unwrap();
+        assert_eq!(cell.sector.centroid(), cell.sector.centre());
+        assert_eq!(cell.validate(), vec![]);
+    }
+
+    #[test]
+    fn flux_lod() {
+        let size = FP128::from_num(1u128 << 60);
+        let mut cell = Cell::new(Aabb::new(Vec3F::ONE * -size, Vec3F::ONE * size), Spectrum::ZERO);
+        let bright = Vec3F::from_f64s(0.5, 0.5, -40.0);
+        let faint = Vec3F::from_f64s(0.5, 0.5, -50.0);
+        let far = Vec3F::from_f64s(0.5, 0.5, -((1u128 << 59) as f64));
+        cell.add_body(Star::new(bright, 0.5.to_fixed(), Spectrum::ONE));
+        cell.add_body(Star::new(faint, 0.5.to_fixed(), Spectrum::ONE * 1e-30));
+        // distant cluster, bright enough to see but far too small to resolve
+        cell.add_body(Star::new(far, 0.5.to_fixed(), Spectrum::ONE * 1e40));
+        cell.add_body(Star::new(far + Vec3F::X, 0.5.to_fixed(), Spectrum::ONE * 1e40));
+
+        let mut view = View { position: Vec3F::from_f64s(0.5, 0.5, 0.5), rotation: glam::Quat::IDENTITY, fovy: 1.0, aspect: 1.0, screen_height: 1080, min_flux: View::DEFAULT_MIN_FLUX };
+        let lights = cell.all_visible_from(&view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut vec![])
+            .into_iter().flat_map(|c| c.bodies).collect::<Vec<_>>();
+
+        let bodies = lights.iter().filter(|l| l.kind.is_some()).map(|l| l.position).collect::<Vec<_>>();
+        assert_eq!(bodies, vec![bright]);
+
+        let aggregates = lights.iter().filter(|l| l.kind.is_none()).collect::<Vec<_>>();
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].spectrum, Spectrum::ONE * 2e40);
+        assert!(aggregates[0].position.distance(far) < aggregates[0].diameter);
+
+        // the colour main.rs hands the shader is scaled down by 1e8, which fs_main scales back up
+        for (spectrum, dist) in [(Spectrum::ONE, 40.0), (Spectrum::ONE * 1e-30, 50.0), (Spectrum::ONE * 2e40, (1u128 << 59) as f64)] {
+            let colour = spectrum.to_rgb().max_element() / 1.0e8;
+            let att = (1.0 + dist) / 1.0e16;
+            let shaded = colour / 1.0e24 / att / att * view.fovy_factor();
+            assert!((view.flux(spectrum, dist) - shaded).abs() <= shaded * 1e-9);
+        }
+
+        // nothing is bright enough
+        view.min_flux = f64::INFINITY;
+        assert!(cell.all_visible_from(&view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut vec![]).is_empty());
+    }
+
+    #[test]
+    fn stats() {
+        let mut bodies = vec![Star::new(Vec3F::ONE * -32.0, 0.5.to_fixed(), Spectrum::ONE)];
+        // share a leaf at max depth, with a chain of cells down to it
+        bodies.extend((0..8).map(|_| Star::new(Vec3F::ONE * 3.0, 0.5.to_fixed(), Spectrum::ONE)));
+        let mut cell = Cell::from_bodies(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO, bodies, TreeConfig::DEFAULT);
+
+        let stats = cell.stats();
+        assert_eq!((stats.cells, stats.leaves, stats.unloaded, stats.bodies), (Cell::MAX_DEPTH, Cell::MAX_DEPTH * 7 + 1, 0, 9));
+        assert_eq!(stats.max_leaf_occupancy, 8);
+        assert_eq!(stats.leaf_occupancy, [(0, Cell::MAX_DEPTH * 7 - 1), (1, 1), (8, 1)].into_iter().collect());
+        assert_eq!(stats.depths.len(), Cell::MAX_DEPTH + 1);
+        assert_eq!(stats.depths[1], DepthStats { cells: 1, leaves: 7, unloaded: 0, bodies: 1 });
+        assert_eq!(stats.depths[Cell::MAX_DEPTH], DepthStats { cells: 0, leaves: 8, unloaded: 0, bodies: 8 });
+        assert!(stats.memory >= Cell::MAX_DEPTH * std::mem::size_of::<Cell>());
+
+        cell.page_out(4, &mut |_| Ok::<_, ()>(())).unwrap();
+        let stats = cell.stats();
+        assert_eq!((stats.cells, stats.unloaded), (4, 1));
+        assert_eq!(stats.depths[4].unloaded, 1);
+    }
+
+    #[test]
+    fn evict() {
+        let bodies = random_stars(3, 512, 64.0);
+        let mut cell = Cell::from_bodies(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO, bodies, TreeConfig::DEFAULT);
+
+        let view = View { position: Vec3F::ONE * 32.0, rotation: glam::Quat::IDENTITY, fovy: 1.0, aspect: 1.0, screen_height: 1080, min_flux: 0.0 };
+        let mut candidates = vec![];
+        let expected = cell.all_visible_from(&view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut candidates);
+        assert!(candidates.iter().all(|c| c.visited <= 1 && c.depth > 0));
+        let candidate = *candidates.iter().find(|c| c.depth == 1).unwrap();
+
+        let memory = cell.memory();
+        assert_eq!(memory, cell.stats().memory);
+
+        // built from bodies, so has to be paged before it can be evicted
+        let mut pages = std::collections::HashMap::new();
+        let freed = cell.evict(candidate.id, &mut |cell| { pages.insert(cell.sector.id, bincode::serialize(cell).unwrap()); Ok::<_, ()>(()) }).unwrap().unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(cell.memory(), memory - freed);
+        assert_eq!(cell.evict(candidate.id, &mut |_| Ok::<_, ()>(())), Ok(None));
+        assert_eq!(cell.evict(Sector::ID_ROOT, &mut |_| Ok::<_, ()>(())), Ok(None));
+
+        let loads = std::sync::atomic::AtomicUsize::new(0);
+        candidates.clear();
+        let visible = cell.all_visible_from(&view, 2, &|id, _, _| { loads.fetch_add(1, std::sync::atomic::Ordering::Relaxed); bincode::deserialize(&pages[&id]).unwrap() }, &mut candidates);
+        assert_eq!(visible, expected);
+        assert_eq!(loads.into_inner(), 1);
+        assert!(candidates.contains(&EvictionCandidate { visited: 2, ..candidate }));
+
+        // cells loaded along with it have no page of their own
+        let Node::Cell(loaded) = &cell.children[(candidate.id % 8) as usize] else { panic!("expected loaded cell") };
+        let inner = loaded.children.iter().find_map(|c| if let Node::Cell(c) = c { Some(c.sector.id) } else { None }).unwrap();
+        assert!(candidates.iter().all(|c| c.id != inner));
+        assert_eq!(cell.evict(inner, &mut |_| Err(())), Ok(None));
+
+        // loaded unmodified, so can be dropped without paging
+        assert!(matches!(cell.evict(candidate.id, &mut |_| Err(())), Ok(Some(_))));
+    }
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    struct Ball {
+        position: Vec3F,
+        radius: f64,
+        kind: BodyKind,
+    }
+
+    impl Body for Ball {
+        fn position(&self) -> Vec3F {
+            self.position
+        }
+
+        fn set_position(&mut self, position: Vec3F) {
+            self.position = position;
+        }
+
+        fn diameter(&self) -> FP128 {
+            (self.radius * 2.0).to_fixed()
+        }
+
+        fn luminosity(&self) -> Spectrum {
+            Spectrum::splat(self.radius)
+        }
+
+        fn kind(&self) -> BodyKind {
+            self.kind
+        }
+    }
+
+    #[test]
+    fn body_kinds() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO);
+        cell.add_body(Ball { position: Vec3F::from_f64s(0.5, 0.5, -40.0), radius: 4.0, kind: BodyKind::Star });
+        cell.add_body(Ball { position: Vec3F::from_f64s(20.5, 0.5, -40.0), radius: 8.0, kind: BodyKind::Galaxy });
+        assert_eq!(cell.validate(), vec![]);
+        assert_eq!(cell.sector.luminosity, Spectrum::splat(12.0));
+
+        let view = View { position: Vec3F::from_f64s(0.5, 0.5, 0.5), rotation: glam::Quat::IDENTITY, fovy: 1.0, aspect: 1.0, screen_height: 1080, min_flux: 0.0 };
+        let mut lights = cell.all_visible_from(&view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut vec![])
+            .into_iter().flat_map(|c| c.bodies).filter(|l| l.kind.is_some()).collect::<Vec<_>>();
+        lights.sort_by_key(|l| l.diameter);
+        assert_eq!(lights.iter().map(|l| (l.kind, l.diameter.to_num::<f64>())).collect::<Vec<_>>(), vec![(Some(BodyKind::Star), 8.0), (Some(BodyKind::Galaxy), 16.0)]);
+
+        // only hit because of its extent, the ray misses the centre
+        let picks = cell.pick(view.position, glam::DVec3::new(0.08, 0.0, -1.0), 0.0, PickOrder::Distance, &mut |_, _, _| unreachable!("no unloaded cells"));
+        assert_eq!(picks.iter().map(|p| (p.body.kind, p.body.radius)).collect::<Vec<_>>(), vec![(BodyKind::Star, 4.0)]);
+        let picks = cell.pick(view.position, glam::DVec3::new(20.0, 0.0, -40.5), 0.0, PickOrder::Distance, &mut |_, _, _| unreachable!("no unloaded cells"));
+        assert_eq!(picks.iter().map(|p| (p.body.kind, p.body.radius)).collect::<Vec<_>>(), vec![(BodyKind::Galaxy, 8.0)]);
+
+        // galaxies report their own kind and extent in a tree of their own
+        let mut galaxies = Cell::<Galaxy>::new(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO);
+        galaxies.add_body(Galaxy { position: Vec3F::from_f64s(0.5, 0.5, -40.0), diameter: 30.to_fixed(), spectrum: Spectrum::ONE });
+        let lights = galaxies.all_visible_from(&view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut vec![])
+            .into_iter().flat_map(|c| c.bodies).filter(|l| l.kind.is_some()).collect::<Vec<_>>();
+        assert_eq!(lights.iter().map(|l| (l.kind, l.diameter.to_num::<f64>())).collect::<Vec<_>>(), vec![(Some(BodyKind::Galaxy), 30.0)]);
+    }
+
+    #[test]
+    fn resolved_discs() {
+        let size = 1.0e16;
+        let mut cell = Cell::new(Aabb::new(Vec3F::ONE * -size, Vec3F::ONE * size), Spectrum::ZERO);
+        let sun = Vec3F::from_f64s(0.0, 0.0, -1.0e11);
+        cell.add_body(Star::new(sun, 7.0e8.to_fixed(), Spectrum::ONE * 1e34));
+        cell.add_body(Star::new(sun * 1.0e4, 7.0e8.to_fixed(), Spectrum::ONE * 1e34));
+
+        let view = View { position: Vec3F::ZERO, rotation: glam::Quat::IDENTITY, fovy: 1.0, aspect: 1.0, screen_height: 1080, min_flux: 0.0 };
+        let mut lights = cell.all_visible_from(&view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut vec![])
+            .into_iter().flat_map(|c| c.bodies).filter(|l| l.kind.is_some()).collect::<Vec<_>>();
+        lights.sort_by_key(|l| -l.position.z);
+        // ~15 pixels across at 1e11 m, a small fraction of a pixel at 1e15 m
+        assert_eq!(lights.iter().map(|l| l.resolved).collect::<Vec<_>>(), vec![true, false]);
+        assert_eq!(lights[0].diameter, 1.4e9.to_fixed::<FP128>());
+    }
+
+    #[test]
+    fn parallel_visibility() {
+        let bodies = random_stars(5, 4096, 64.0);
+        let build = || {
+            let mut cell = Cell::from_bodies(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO, bodies.clone(), TreeConfig::DEFAULT);
+            // a paged subtree and an empty one still to be generated
+            let mut pages = std::collections::HashMap::new();
+            cell.evict(Sector::id_push(Sector::ID_ROOT, Octant::PxPyPz), &mut |cell| { pages.insert(cell.sector.id, bincode::serialize(cell).unwrap()); Ok::<_, ()>(()) }).unwrap();
+            cell.children[Octant::NxNyNz as usize] = Node::Unloaded(Sector::id_push(Sector::ID_ROOT, Octant::NxNyNz));
+            (cell, pages)
+        };
+        let (mut sequential, pages) = build();
+        let (mut parallel, _) = build();
+        let load = |id: u128, bounds: Aabb, _| match pages.get(&id) {
+            Some(page) => bincode::deserialize(page).unwrap(),
+            None => Cell::from_bodies_at(id, bounds, Spectrum::ZERO, vec![Star::new(bounds.min, 0.5.to_fixed(), Spectrum::ONE)], TreeConfig::DEFAULT),
+        };
+
+        let view = View { position: Vec3F::ONE * 8.0, rotation: glam::Quat::from_rotation_y(2.5), fovy: 1.5, aspect: 1.5, screen_height: 1080, min_flux: 0.0 };
+        let (mut sequential_candidates, mut parallel_candidates) = (vec![], vec![]);
+        let expected = sequential.visible_from(&view, 1, &load, 0, &mut sequential_candidates);
+        assert!(expected.iter().map(|c| c.bodies.len()).sum::<usize>() > 500);
+        assert_eq!(parallel.visible_from(&view, 1, &load, Cell::MAX_DEPTH, &mut parallel_candidates), expected);
+        sequential_candidates.sort_by_key(|c| c.id);
+        parallel_candidates.sort_by_key(|c| c.id);
+        assert_eq!(parallel_candidates, sequential_candidates);
+        assert_eq!(parallel.all_visible_from(&view, 2, &load, &mut vec![]), expected);
+        assert!(parallel.children.iter().all(|c| matches!(c, Node::Cell(_))));
+    }
+
+    #[test]
+    fn page_out() {
+        let mut cell = Cell::new(Aabb::new(Vec3F::ZERO, Vec3F::ONE * 64.0), Spectrum::ZERO);
+        for i in 1..64 {
+            let f = i as f64;
+            cell.add_body(Star::new(Vec3F::from_f64s(f, (f * 7.0) % 64.0, (f * 13.0) % 64.0), 0.5.to_fixed(), Spectrum::ONE));
+        }
+
+        let view = View { position: Vec3F::ONE * 32.0, rotation: glam::Quat::IDENTITY, fovy: 1.0, aspect: 1.0, screen_height: 1080, min_flux: View::DEFAULT_MIN_FLUX };
+        let expected = cell.all_visible_from(&view, 1, &|_, _, _| unreachable!("no unloaded cells"), &mut vec![]);
+
+        let mut pages = std::collections::HashMap::new();
+        cell.page_out(2, &mut |c: Cell| {
+            pages.insert(c.sector().id(), bincode::serialize(&c)?);
+            Ok::<_, bincode::Error>(())
+        }).unwrap();
+        assert!(!pages.is_empty());
+
+        let mut paged = bincode::deserialize::<Cell>(&bincode::serialize(&cell).unwrap()).unwrap();
+        let visible = paged.all_visible_from(&view, 1, &|id, _, _| bincode::deserialize(&pages[&id]).unwrap(), &mut vec![]);
+
+        assert_eq!(expected, visible);
+    }
 }
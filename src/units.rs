@@ -0,0 +1,209 @@
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+use fixed::traits::ToFixed;
+
+use crate::fp::{Vec3F, FP128};
+
+/// defines a length unit wrapping a scalar (`f64`) or a vector (`glam::DVec3`),
+/// which only converts to other units through the engine's metres, so lengths in different units can't be mixed by accident
+macro_rules! length_unit {
+    ($(#[$meta:meta])* $name:ident, $metres:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default, serde::Serialize, serde::Deserialize)]
+        #[serde(transparent)]
+        pub struct $name<T = f64>(pub T);
+
+        #[allow(unused)]
+        impl $name {
+            /// length of one unit in metres
+            pub const METRES: f64 = $metres;
+        }
+
+        #[allow(unused)]
+        impl<T> $name<T> {
+            /// convert to another unit, e.g. `Parsecs(1.0).to::<LightYears>()`
+            pub fn to<U: From<Metres<T>>>(self) -> U where Metres<T>: From<Self> {
+                U::from(Metres::from(self))
+            }
+        }
+
+        impl<T: Add<Output = T>> Add for $name<T> {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl<T: Sub<Output = T>> Sub for $name<T> {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        impl<T: AddAssign> AddAssign for $name<T> {
+            fn add_assign(&mut self, rhs: Self) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl<T: SubAssign> SubAssign for $name<T> {
+            fn sub_assign(&mut self, rhs: Self) {
+                self.0 -= rhs.0;
+            }
+        }
+
+        impl<T: Neg<Output = T>> Neg for $name<T> {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+
+        impl<T: Mul<f64, Output = T>> Mul<f64> for $name<T> {
+            type Output = Self;
+
+            fn mul(self, rhs: f64) -> Self {
+                Self(self.0 * rhs)
+            }
+        }
+
+        impl<T: Div<f64, Output = T>> Div<f64> for $name<T> {
+            type Output = Self;
+
+            fn div(self, rhs: f64) -> Self {
+                Self(self.0 / rhs)
+            }
+        }
+
+        impl From<$name<f64>> for FP128 {
+            fn from(value: $name<f64>) -> Self {
+                (value.0 * $name::METRES).to_fixed()
+            }
+        }
+
+        impl From<$name<glam::DVec3>> for Vec3F {
+            fn from(value: $name<glam::DVec3>) -> Self {
+                Vec3F::from_dvec3(value.0 * $name::METRES)
+            }
+        }
+
+        impl From<$name<f64>> for Metres<FP128> {
+            fn from(value: $name<f64>) -> Self {
+                Metres(value.into())
+            }
+        }
+
+        impl From<$name<glam::DVec3>> for Metres<Vec3F> {
+            fn from(value: $name<glam::DVec3>) -> Self {
+                Metres(value.into())
+            }
+        }
+
+        impl From<FP128> for $name<f64> {
+            fn from(value: FP128) -> Self {
+                Self(value.to_num::<f64>() / $name::METRES)
+            }
+        }
+
+        impl From<Vec3F> for $name<glam::DVec3> {
+            fn from(value: Vec3F) -> Self {
+                Self(value.to_dvec3() / $name::METRES)
+            }
+        }
+    };
+}
+
+length_unit!(
+    /// length in metres, the unit of `FP128` and `Vec3F` positions
+    Metres, 1.0
+);
+length_unit!(
+    /// length in astronomical units
+    Au, 1.495978707e11
+);
+length_unit!(
+    /// length in julian light-years
+    LightYears, 9.4607304725808e15
+);
+length_unit!(
+    /// length in parsecs, the unit of catalogue positions
+    Parsecs, 3.085_677_581_491_367e16
+);
+
+// engine lengths and positions are already in metres, `Metres` only marks them as such
+impl From<Metres<FP128>> for FP128 {
+    fn from(value: Metres<FP128>) -> Self {
+        value.0
+    }
+}
+
+impl From<Metres<Vec3F>> for Vec3F {
+    fn from(value: Metres<Vec3F>) -> Self {
+        value.0
+    }
+}
+
+/// velocity in metres per second, the unit of `Body::velocity`
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct MetresPerSecond<T = f64>(pub T);
+
+impl From<MetresPerSecond<glam::DVec3>> for glam::DVec3 {
+    fn from(value: MetresPerSecond<glam::DVec3>) -> Self {
+        value.0
+    }
+}
+
+/// conversions between metres and the other units, which `to` goes through
+macro_rules! metres_conversions {
+    ($($name:ident),*) => {
+        $(
+            impl<T: Mul<f64, Output = T>> From<$name<T>> for Metres<T> {
+                fn from(value: $name<T>) -> Self {
+                    Metres(value.0 * $name::METRES)
+                }
+            }
+
+            impl<T: Div<f64, Output = T>> From<Metres<T>> for $name<T> {
+                fn from(value: Metres<T>) -> Self {
+                    $name(value.0 / $name::METRES)
+                }
+            }
+        )*
+    };
+}
+
+metres_conversions!(Au, LightYears, Parsecs);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let close = |a: f64, b: f64| (a - b).abs() <= b.abs() * 1e-12;
+
+        // a parsec is the distance at which an AU subtends one arcsecond
+        assert!(close(Parsecs(1.0).to::<Au>().0, 648000.0 / std::f64::consts::PI));
+        assert!(close(Parsecs(1.0).to::<LightYears>().0, 3.261_563_777_167_4));
+        assert!(close(LightYears(1.0).to::<Metres>().0, 9.4607304725808e15));
+        assert_eq!(Metres(2.0).to::<Metres>(), Metres(2.0));
+
+        // round trips through fixed point
+        let pos = Parsecs(glam::dvec3(-1.3, 0.0, 778.0));
+        let back = Parsecs::<glam::DVec3>::from(Vec3F::from(pos));
+        assert!((back.0 - pos.0).abs().max_element() < 1e-9);
+        assert_eq!(FP128::from(Au(1.0)), FP128::from_num(Au::METRES));
+        assert!(close(Au::from(FP128::from(Au(42.0))).0, 42.0));
+        assert_eq!(FP128::from(Metres::<FP128>::from(Au(1.0))), FP128::from(Au(1.0)));
+        assert_eq!(Vec3F::from(Metres::<Vec3F>::from(pos)), Vec3F::from(pos));
+
+        // arithmetic stays within a unit
+        assert_eq!(Parsecs(1.0) + Parsecs(2.0) * 2.0, Parsecs(5.0));
+        assert_eq!(-(LightYears(glam::DVec3::X) - LightYears(glam::DVec3::Y)) / 2.0, LightYears(glam::dvec3(-0.5, 0.5, 0.0)));
+    }
+}
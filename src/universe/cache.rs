@@ -0,0 +1,161 @@
+use std::{
+    fs,
+    hash::Hasher,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{bufread::GzDecoder, write::GzEncoder};
+
+use crate::tree::{Body, Cell};
+
+pub const CACHE_PATH: &str = "data/octree.bin.gz";
+
+/// changes whenever the layout of a cached octree does, so caches written by older engines are rebuilt
+const CACHE_FORMAT: u32 = 4;
+
+/// octree populated from the star catalogues, saved so later launches don't have to rebuild it.
+/// the cache is only used if it was written by the same engine version from the same inputs, see `CacheKey`
+pub struct OctreeCache {
+    path: PathBuf,
+}
+
+/// written before the octree, checked before it is read
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct CacheHeader {
+    format: u32,
+    engine_version: String,
+    key: u64,
+}
+
+impl CacheHeader {
+    fn new(key: u64) -> CacheHeader {
+        CacheHeader {
+            format: CACHE_FORMAT,
+            engine_version: std::env!("CARGO_PKG_VERSION").into(),
+            key,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("io error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("parse error: {0}")]
+    BinParseError(#[from] bincode::Error),
+}
+
+impl OctreeCache {
+    pub fn new(path: impl Into<PathBuf>) -> OctreeCache {
+        OctreeCache { path: path.into() }
+    }
+
+    /// load the cached octree, returns `None` if there is no cache or it was built from different inputs
+    pub fn load<B: Body>(&self, key: u64) -> Result<Option<Cell<B>>, CacheError> {
+        log::trace!("loading octree cache {:?}", self.path);
+
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut reader = io::BufReader::new(GzDecoder::new(io::BufReader::new(file)));
+
+        let header: CacheHeader = bincode::deserialize_from(&mut reader)?;
+        if header != CacheHeader::new(key) {
+            log::info!("octree cache {:?} is out of date", self.path);
+            return Ok(None);
+        }
+
+        Ok(Some(bincode::deserialize_from(reader)?))
+    }
+
+    /// write the octree to the cache, replacing any previous cache
+    pub fn save<B: Body>(&self, key: u64, cell: &Cell<B>) -> Result<(), CacheError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        log::trace!("saving octree cache {:?}", self.path);
+
+        // written alongside and moved into place, so an interrupted save never leaves a truncated cache
+        let partial = self.path.with_extension("partial");
+
+        let mut writer = GzEncoder::new(io::BufWriter::new(fs::File::create(&partial)?), Default::default());
+        {
+            let mut buffered = io::BufWriter::new(&mut writer);
+            bincode::serialize_into(&mut buffered, &CacheHeader::new(key))?;
+            bincode::serialize_into(&mut buffered, cell)?;
+            buffered.flush()?;
+        }
+        writer.finish()?.flush()?;
+
+        fs::rename(partial, &self.path)?;
+
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// 64 bit FNV-1a, unlike `DefaultHasher` it gives the same key on every run and build
+pub struct CacheKey(u64);
+
+impl Default for CacheKey {
+    fn default() -> CacheKey {
+        CacheKey(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for CacheKey {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fp::{Aabb, Vec3F}, spectrum::Spectrum, tree::{random_stars, Star}};
+
+    use super::*;
+
+    #[test]
+    fn cache_key() {
+        // reference values for 64 bit FNV-1a
+        let mut key = CacheKey::default();
+        assert_eq!(key.finish(), 0xcbf29ce484222325);
+        key.write(b"a");
+        assert_eq!(key.finish(), 0xaf63dc4c8601ec8c);
+        key.write(b"bc");
+        assert_eq!(key.finish(), 0xe71fa2190541574b);
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("universe-engine-octree-cache-{}.bin.gz", std::process::id()));
+        let cache = OctreeCache::new(&path);
+
+        let bodies = random_stars(6, 256, 64.0);
+        let cell = Cell::from_bodies(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO, bodies, crate::tree::TreeConfig::DEFAULT);
+
+        assert!(cache.load::<Star>(1).unwrap().is_none());
+        cache.save(1, &cell).unwrap();
+
+        let loaded = cache.load::<Star>(1).unwrap().expect("cache was just saved");
+        assert_eq!(bincode::serialize(&loaded).unwrap(), bincode::serialize(&cell).unwrap());
+        // built from other inputs
+        assert!(cache.load::<Star>(2).unwrap().is_none());
+
+        fs::remove_file(path).unwrap();
+    }
+}
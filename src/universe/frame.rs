@@ -0,0 +1,122 @@
+use crate::{fp::{Vec3F, FP128}, units::Metres};
+
+/// obliquity of the ecliptic at J2000, in degrees
+pub const OBLIQUITY: f64 = 23.439_291_1;
+
+/// rows of the rotation from equatorial (J2000) to galactic cartesian coordinates, from the Hipparcos catalogue
+const EQUATORIAL_TO_GALACTIC: [f64; 9] = [
+    -0.054_875_560_416_215, -0.873_437_090_234_885, -0.483_835_015_548_713,
+    0.494_109_427_875_584, -0.444_829_629_960_011, 0.746_982_244_497_219,
+    -0.867_666_149_019_005, -0.198_076_373_431_201, 0.455_983_776_175_067,
+];
+
+/// celestial reference frame, positions are given as a longitude and latitude in degrees and a distance,
+/// or as cartesian coordinates with x towards longitude 0, latitude 0, y towards longitude 90 and z towards latitude 90.
+/// the engine's own frame is equatorial centred on earth, other frames are only rotated, not moved to the sun or galactic centre
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+pub enum Frame {
+    /// right ascension and declination (J2000)
+    #[default]
+    Equatorial,
+    /// galactic longitude and latitude
+    Galactic,
+    /// ecliptic longitude and latitude (J2000)
+    Ecliptic,
+}
+
+#[allow(unused)]
+impl Frame {
+    /// rotation taking cartesian coordinates in this frame to the engine's frame
+    pub fn rotation(self) -> glam::DMat3 {
+        match self {
+            Frame::Equatorial => glam::DMat3::IDENTITY,
+            // rows read as columns give the transpose, which is the inverse
+            Frame::Galactic => glam::DMat3::from_cols_array(&EQUATORIAL_TO_GALACTIC),
+            Frame::Ecliptic => glam::DMat3::from_rotation_x(OBLIQUITY.to_radians()),
+        }
+    }
+
+    /// cartesian coordinates in this frame to the engine's frame
+    pub fn to_engine(self, value: glam::DVec3) -> glam::DVec3 {
+        self.rotation() * value
+    }
+
+    /// cartesian coordinates in the engine's frame to this frame
+    pub fn to_frame(self, value: glam::DVec3) -> glam::DVec3 {
+        self.rotation().transpose() * value
+    }
+
+    /// unit vector in the engine's frame towards `lon`, `lat` (degrees) in this frame
+    pub fn direction(self, lon: f64, lat: f64) -> glam::DVec3 {
+        let (lon, lat) = (lon.to_radians(), lat.to_radians());
+        self.to_engine(glam::dvec3(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()))
+    }
+
+    /// longitude in [0, 360) and latitude in [-90, 90] degrees in this frame of a direction in the engine's frame
+    pub fn angles(self, direction: glam::DVec3) -> (f64, f64) {
+        let v = self.to_frame(direction);
+        let lon = v.y.atan2(v.x).to_degrees().rem_euclid(360.0);
+        let lat = v.z.atan2(v.x.hypot(v.y)).to_degrees();
+        (lon, lat)
+    }
+
+    /// position in the engine's frame `dist` away towards `lon`, `lat` (degrees) in this frame
+    pub fn position(self, lon: f64, lat: f64, dist: Metres<FP128>) -> Metres<Vec3F> {
+        Metres(Vec3F::from_dvec3(self.direction(lon, lat) * FP128::from(dist).to_num::<f64>()))
+    }
+
+    /// longitude, latitude (degrees) in this frame and distance of a position in the engine's frame
+    pub fn coordinates(self, position: Metres<Vec3F>) -> (f64, f64, Metres<FP128>) {
+        let position = Vec3F::from(position);
+        if position == Vec3F::ZERO {
+            return (0.0, 0.0, Metres(FP128::ZERO));
+        }
+        let (lon, lat) = self.angles(position.direction());
+        (lon, lat, Metres(position.length()))
+    }
+
+    /// `lon`, `lat` (degrees) in this frame to longitude and latitude in `other`
+    pub fn convert(self, other: Frame, lon: f64, lat: f64) -> (f64, f64) {
+        other.angles(self.direction(lon, lat))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::units::Parsecs;
+
+    #[test]
+    fn frames() {
+        let close = |(a, b): (f64, f64), (c, d): (f64, f64)| {
+            let dlon = (a - c + 180.0).rem_euclid(360.0) - 180.0;
+            dlon.abs() < 1e-3 && (b - d).abs() < 1e-3
+        };
+
+        // galactic centre and north galactic pole
+        assert!(close(Frame::Equatorial.convert(Frame::Galactic, 266.404_99, -28.936_17), (0.0, 0.0)));
+        assert_eq!(Frame::Equatorial.convert(Frame::Galactic, 192.859_48, 27.128_25).1.round(), 90.0);
+        assert!(close(Frame::Galactic.convert(Frame::Equatorial, 0.0, 0.0), (266.404_99, -28.936_17)));
+
+        // the equinox lies on both the equator and ecliptic, the north ecliptic pole is at 18h
+        assert!(close(Frame::Equatorial.convert(Frame::Ecliptic, 0.0, 0.0), (0.0, 0.0)));
+        assert!(close(Frame::Ecliptic.convert(Frame::Equatorial, 0.0, 90.0), (270.0, 90.0 - OBLIQUITY)));
+        assert!(close(Frame::Ecliptic.convert(Frame::Equatorial, 90.0, 0.0), (90.0, OBLIQUITY)));
+
+        for frame in [Frame::Equatorial, Frame::Galactic, Frame::Ecliptic] {
+            assert!((frame.rotation() * frame.rotation().transpose()).abs_diff_eq(glam::DMat3::IDENTITY, 1e-12));
+            assert!(close(frame.angles(frame.direction(123.0, -45.0)), (123.0, -45.0)));
+        }
+
+        // andromeda, as converted by hand in andromeda.txt
+        let andromeda = Frame::Equatorial.position(0.186_459_341_754_727_5_f64.to_degrees(), 0.720_282_837_887_626_5_f64.to_degrees(), Metres(2.4002557e22).into());
+        let expected = Vec3F::from_f64s(1.772_807_753_432_297_6e22, 3.344_414_314_330_111e21, 1.583_202_142_398_076_2e22);
+        assert!(Vec3F::from(andromeda).distance(expected) < FP128::from_num(1e12));
+        assert!((Parsecs::<glam::DVec3>::from(Vec3F::from(andromeda)).0.length() - 777_870.0).abs() < 1.0);
+
+        let (lon, lat, dist) = Frame::Galactic.coordinates(andromeda);
+        assert!(Vec3F::from(Frame::Galactic.position(lon, lat, dist)).distance(andromeda.into()) < FP128::from_num(1e9));
+        assert_eq!(Frame::Galactic.coordinates(Metres(Vec3F::ZERO)), (0.0, 0.0, Metres(FP128::ZERO)));
+    }
+}
@@ -1,11 +1,11 @@
 use std::{
     fs,
+    hash::{Hash, Hasher},
     io::{self, BufRead},
     path::{Path, PathBuf},
 };
 
 use flate2::bufread::GzDecoder;
-use serde::de::DeserializeOwned;
 
 pub const MOD_DIR: &'static str = "data/mods";
 pub const LOAD_ORDER: &'static str = "load_order.txt";
@@ -34,6 +34,14 @@ pub enum ModError {
     BinParseError(#[from] bincode::Error),
     #[error("load order does any mods")]
     Empty,
+    #[error("{0:?} was encoded by an engine with another catalogue format, encode it again with `encode-catalogue`")]
+    CatalogueFormat(PathBuf),
+}
+
+/// decompress the contents of a compressed mod file
+pub fn decompress(contents: &[u8]) -> impl io::Read + '_ {
+    // bincode reads a few bytes at a time, which is slow straight from the decoder
+    io::BufReader::new(GzDecoder::new(contents))
 }
 
 impl ModFs {
@@ -92,15 +100,27 @@ impl ModFs {
         Ok(dir_contents.into_iter().map(|(p, _)| p).collect())
     }
 
-    pub fn decompress_bin<T: DeserializeOwned>(&self, file: impl AsRef<Path>) -> Result<T, ModError> {
+    /// path of the file in the last mod in the load order that has it
+    fn resolve(&self, file: &Path) -> io::Result<PathBuf> {
+        let (_, mod_path) = self.mods.iter().filter(|(_, p)| p.join(file).exists()).last().ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+        Ok(mod_path.join(file))
+    }
+
+    /// contents of the file in the last mod in the load order that has it
+    pub fn read(&self, file: impl AsRef<Path>) -> io::Result<Vec<u8>> {
         let file = file.as_ref();
 
-        log::trace!("decompressing binary ({}) {file:?}", std::any::type_name::<T>());
-        
-        let (_, mod_path) = self.mods.iter().filter(|(_, p)| p.join(file).exists()).last().ok_or(io::Error::from(io::ErrorKind::NotFound))?;
-        let file = fs::File::open(mod_path.join(file))?;
-        let reader = GzDecoder::new(io::BufReader::new(file));
-        
-        Ok(bincode::deserialize_from(reader)?)
+        log::trace!("reading {file:?}");
+
+        fs::read(self.resolve(file)?)
+    }
+
+    /// feed the load order (names and versions of the loaded mods) into `state`,
+    /// so that anything built from their files can tell when a mod has changed. where the mods are installed doesn't matter
+    pub fn hash_mods(&self, state: &mut impl Hasher) {
+        for (meta, _) in &self.mods {
+            meta.name.hash(state);
+            meta.version.hash(state);
+        }
     }
 }
@@ -1,37 +1,13 @@
-use std::io;
+use std::{collections::{BTreeSet, HashMap, HashSet}, hash::{Hash, Hasher}, io, sync::{atomic::{self, AtomicUsize}, Mutex}};
 
-use crate::{fp::{Vec3F, FP128}, tree::{Body, Cell, CellVisibility}};
+use crate::{fp::{Aabb, Vec3F, FP128}, spectrum::Spectrum, units::{Metres, MetresPerSecond, Parsecs}, tree::{Cell, CellVisibility, EvictionCandidate, Galaxy, Pick, PickOrder, Sector, Star, TreeConfig, TreeStats, View, Violation}};
 
-use self::fs::{ModError, ModFs};
+use self::{cache::{CacheKey, OctreeCache}, frame::Frame, fs::{ModError, ModFs}, store::{CellStore, StoreError}};
 
+pub mod cache;
+pub mod frame;
 pub mod fs;
-
-/// approximation of black body spectrum (normalised)
-fn black_body(wavelength: f64, temp: f64) -> f64 {
-    let peak = 2897771.955 / temp;
-    let x_scale = 6.8e-8;
-    let y_scale = peak.powi(5) * ((1.0/(peak*x_scale*temp)).exp() - 1.0);
-    let denom = wavelength.powi(5) * (1.0/(wavelength*x_scale*temp)).exp() - 1.0;
-    return y_scale/denom;
-}
-
-/// area of a gaussian with sd (b-a)/2, and mean (b+a)/2
-fn gaussian_area(temp: f64, a: f64, b: f64) -> f64 {
-    let mid = (a + b) / 2.0;
-    let peak = black_body(mid, temp);
-    return peak * (b - a) * (2.0 * std::f64::consts::PI).sqrt();
-}
-
-/// approximate black body rgb colour given star temperature (multiply with brightness to get luminance)
-fn temperature_rgb(temp: f64) -> glam::DVec3 {
-    let r = gaussian_area(temp, 520.0, 630.0);
-    let g = gaussian_area(temp, 500.0, 590.0);
-    let b = gaussian_area(temp, 410.0, 480.0);
-
-    let v = glam::dvec3(r, g, b);
-
-    return v / v.max_element().max(0.00001);
-}
+pub mod store;
 
 /// B-V colour index to temperature
 fn ci_temperature(b_v_index: f64) -> f64 {
@@ -47,37 +23,345 @@ fn abs_mag_brightness(abs_mag: f64) -> f64 {
     2.512f64.powf(-abs_mag) * 1.0e36
 }
 
-fn generate_cell(id: u128, bounds: (Vec3F, Vec3F), luminosity: glam::DVec3) -> Cell {
-    println!("generating cell {id}");
-    Cell::new(bounds.0, bounds.0, luminosity)
+/// luminosity of the sun in watts
+const SUN_LUMINOSITY: f64 = 3.828e26;
+/// stefan-boltzmann constant in W m^-2 K^-4
+const STEFAN_BOLTZMANN: f64 = 5.670374419e-8;
+
+/// radius in metres of a black body with the given temperature and absolute magnitude, from L = 4 pi R^2 sigma T^4.
+/// visual magnitudes stand in for bolometric ones, which undersizes very hot and very cool stars
+fn stellar_radius(temp: f64, abs_mag: f64) -> f64 {
+    let luminosity = SUN_LUMINOSITY * 10.0f64.powf(0.4 * (SUN_ABS_MAG - abs_mag));
+    (luminosity / (4.0 * std::f64::consts::PI * STEFAN_BOLTZMANN * temp.powi(4))).sqrt()
+}
+
+// changing any of these changes every generated cell
+/// mixed into the seed of every generated cell
+const GENERATOR_SEED: u64 = 0x5eed_ce11;
+/// most bodies generated for a single cell
+const MAX_GENERATED_BODIES: usize = 1024;
+/// absolute magnitude of the sun
+const SUN_ABS_MAG: f64 = 4.83;
+
+/// seconds in a julian year, epochs are counted in these from J2000
+pub const JULIAN_YEAR: f64 = 365.25 * 86400.0;
+
+/// procedurally populate a cell that was never stored, the same id always generates the same bodies.
+/// the cell's luminosity matches the budget handed down by the parent in every bin. a budget worth more than
+/// `MAX_GENERATED_BODIES` stars is split between 8 children generated as they are reached, until `config.max_depth`,
+/// where the cell is capped at that many stars and the rest of the budget is left as diffuse light.
+/// the cell is marked unsettled, see `Cell::settle`
+fn generate_cell(id: u128, bounds: Aabb, luminosity: Spectrum, config: TreeConfig) -> Cell {
+    use rand::{Rng, SeedableRng};
+
+    log::trace!("generating cell {id}");
+
+    if luminosity.max_element() <= 0.0 {
+        return Cell::from_bodies_at(id, bounds, luminosity, vec![], config);
+    }
+
+    // roughly as many sun-like stars as the budget allows
+    let sun = abs_mag_brightness(SUN_ABS_MAG) * 3.0;
+    let count = (luminosity.to_rgb().element_sum() / sun).round().max(1.0);
+    if count > MAX_GENERATED_BODIES as f64 {
+        let cell = Cell::with_unloaded_children(id, bounds, luminosity, config);
+        if cell.sector().depth() < config.max_depth {
+            return cell;
+        }
+    }
+    let count = count.min(MAX_GENERATED_BODIES as f64) as usize;
+
+    // chacha is portable, unlike `StdRng`, so the same seed generates the same cell on every platform and version
+    let mut seed = [0; 32];
+    seed[..16].copy_from_slice(&id.to_le_bytes());
+    seed[16..24].copy_from_slice(&GENERATOR_SEED.to_le_bytes());
+    let mut rng = rand_chacha::ChaCha8Rng::from_seed(seed);
+
+    let Aabb { min, max } = bounds;
+    let size = bounds.size().to_dvec3();
+
+    let mut bodies = (0..count).map(|_| {
+        let position = min + Vec3F::from_dvec3(size * glam::dvec3(rng.gen(), rng.gen(), rng.gen()));
+        // rounding can land on the exclusive max
+        let position = Vec3F::new(position.x.min(max.x - FP128::DELTA), position.y.min(max.y - FP128::DELTA), position.z.min(max.z - FP128::DELTA));
+
+        // cool stars are far more common than hot ones, and dimmer along the main sequence
+        let colour_index = -0.3 + 2.3 * rng.gen::<f64>().sqrt();
+        let abs_mag = 1.5 + 5.0 * colour_index + (rng.gen::<f64>() + rng.gen::<f64>() - 1.0);
+        let temperature = ci_temperature(colour_index);
+
+        Star::new(position, FP128::from_num(stellar_radius(temperature, abs_mag)), Spectrum::black_body(temperature, abs_mag_brightness(abs_mag)))
+    }).collect::<Vec<_>>();
+
+    // scale every star by the same amount, so they aren't recoloured, as far as the budget allows in every bin.
+    // what's left over in each bin is diffuse light
+    let total = bodies.iter().map(|b| b.spectrum).sum::<Spectrum>();
+    let scale = luminosity.bins().into_iter().zip(total.bins()).filter(|(_, t)| *t > 0.0).map(|(l, t)| l / t).fold(f64::INFINITY, f64::min);
+    let scale = if scale.is_finite() { scale } else { 0.0 };
+    for body in &mut bodies {
+        body.spectrum *= scale;
+    }
+    let diffuse = Spectrum::new((luminosity - total * scale).bins().map(|l| l.max(0.0)));
+
+    let mut cell = Cell::from_bodies_at(id, bounds, diffuse, bodies, config);
+    cell.set_unsettled();
+    cell
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UniverseError {
+    #[error("mod error: {0}")]
+    ModError(#[from] ModError),
+    #[error("cell store error: {0}")]
+    StoreError(#[from] StoreError),
+}
+
+/// load a cell evicted to the scratch store, or else from the store, generating it if it was never paged out.
+/// cells generated are counted in `generated`, the tree must be settled after them, see `Cell::settle`
+fn load_cell(scratch: &CellStore, store: &CellStore, config: TreeConfig, generated: &AtomicUsize, id: u128, bounds: Aabb, luminosity: Spectrum) -> Cell {
+    let stored = scratch.load(id).transpose().or_else(|| store.load(id).transpose());
+    let generate = || {
+        generated.fetch_add(1, atomic::Ordering::Relaxed);
+        generate_cell(id, bounds, luminosity, config)
+    };
+    match stored {
+        Some(Ok(cell)) => cell,
+        None => generate(),
+        Some(Err(err)) => {
+            log::error!("failed to load cell {id}, generating instead: {err}");
+            generate()
+        },
+    }
+}
+
+/// loads the cells reached by a pass over the octree, every pass loads through one so they are all counted and evicted alike.
+/// cells may be loaded from several threads at once
+struct Loader<'a> {
+    scratch: &'a CellStore,
+    store: &'a CellStore,
+    config: TreeConfig,
+    /// the pass, cells loaded by it are as recently visited as the cells it reached
+    visit: u64,
+    evicted: Mutex<&'a mut HashSet<u128>>,
+    reloads: AtomicUsize,
+    memory: AtomicUsize,
+    generated: AtomicUsize,
+    loaded: Mutex<Vec<EvictionCandidate>>,
+}
+
+impl<'a> Loader<'a> {
+    fn new(scratch: &'a CellStore, store: &'a CellStore, config: TreeConfig, visit: u64, evicted: &'a mut HashSet<u128>) -> Self {
+        Loader {
+            scratch,
+            store,
+            config,
+            visit,
+            evicted: Mutex::new(evicted),
+            reloads: AtomicUsize::new(0),
+            memory: AtomicUsize::new(0),
+            generated: AtomicUsize::new(0),
+            loaded: Mutex::new(vec![]),
+        }
+    }
+
+    fn load(&self, id: u128, bounds: Aabb, luminosity: Spectrum) -> Cell {
+        if self.evicted.lock().unwrap_or_else(|e| e.into_inner()).remove(&id) {
+            self.reloads.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+        let cell = load_cell(self.scratch, self.store, self.config, &self.generated, id, bounds, luminosity);
+        self.memory.fetch_add(cell.memory(), atomic::Ordering::Relaxed);
+        self.loaded.lock().unwrap_or_else(|e| e.into_inner()).push(EvictionCandidate { id, depth: cell.sector().depth(), visited: self.visit });
+        cell
+    }
+}
+
+/// what a `Loader` loaded, see `Universe::finish_pass`
+struct Loads {
+    reloads: usize,
+    memory: usize,
+    generated: usize,
+    loaded: Vec<EvictionCandidate>,
+}
+
+impl From<Loader<'_>> for Loads {
+    fn from(loader: Loader) -> Self {
+        Loads {
+            reloads: loader.reloads.into_inner(),
+            memory: loader.memory.into_inner(),
+            generated: loader.generated.into_inner(),
+            loaded: loader.loaded.into_inner().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+}
+
+/// change in the visible set between two visibility queries
+#[derive(Debug, Default)]
+pub struct VisibilityDelta {
+    /// cells that were not visible last query
+    pub added: Vec<CellVisibility>,
+    /// cells visible last query, whose lights have changed
+    pub changed: Vec<CellVisibility>,
+    /// ids of cells that are no longer visible
+    pub removed: Vec<u128>,
+}
+
+impl VisibilityDelta {
+    /// diff `visible` against the `previous` query, keyed by sector id, and replace it
+    fn update(previous: &mut HashMap<u128, CellVisibility>, visible: Vec<CellVisibility>) -> VisibilityDelta {
+        let mut delta = VisibilityDelta::default();
+        let mut current = HashMap::with_capacity(visible.len());
+
+        for cell in visible {
+            match previous.remove(&cell.id) {
+                None => delta.added.push(cell.clone()),
+                Some(old) if old != cell => delta.changed.push(cell.clone()),
+                Some(_) => {},
+            }
+            current.insert(cell.id, cell);
+        }
+
+        delta.removed.extend(previous.keys());
+        *previous = current;
+
+        delta
+    }
+}
+
+/// how often cells have been evicted from memory and loaded again, see `Universe::set_memory_budget`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvictionCounters {
+    pub evictions: usize,
+    pub reloads: usize,
 }
 
 pub struct Universe {
     root: Cell,
+    store: CellStore,
+    /// cells evicted from memory that couldn't be loaded or generated again as they were, deleted along with the universe
+    scratch: CellStore,
+    /// result of the last visibility query, by sector id
+    visible: HashMap<u128, CellVisibility>,
+    /// number of visibility queries so far
+    visit: u64,
+    /// estimated memory the loaded octree may use before cells are evicted, in bytes
+    memory_budget: Option<usize>,
+    /// estimated memory used by the loaded octree, in bytes
+    memory: usize,
+    /// cells that may be evicted, least recently visited and then shallowest first
+    lru: BTreeSet<(u64, usize, u128)>,
+    /// position of each cell in `lru`
+    lru_keys: HashMap<u128, (u64, usize)>,
+    /// cells evicted and not yet loaded again
+    evicted: HashSet<u128>,
+    counters: EvictionCounters,
+    /// seconds since J2000, the epoch of the star catalogues
+    epoch: f64,
+    config: TreeConfig,
 }
 
 impl Universe {
     // pub const REGION_SIZE: FP128 = fixed_macro::fixed!(1208925819614629174706176: I96F32); // 2^80m, roughly 128 million light years
     pub const REGION_SIZE: FP128 = fixed_macro::fixed!(4951760157141521099596496896: I96F32); // 2^92m, roughly 523 billion light years, 5.63 times the size of the observable universe
 
-    pub fn new() -> Result<Universe, ModError> {
+    /// bounds of the whole octree, centred on the origin
+    pub fn region() -> Aabb {
+        Aabb::from_centre_size(Vec3F::ZERO, Vec3F::splat(Self::REGION_SIZE))
+    }
+
+    fn with_root(mut root: Cell, store: CellStore, config: TreeConfig) -> Universe {
+        root.set_config(config);
+        Universe {
+            memory: root.memory(),
+            root,
+            store,
+            scratch: CellStore::new(store::scratch_dir()),
+            visible: HashMap::new(),
+            visit: 0,
+            memory_budget: None,
+            lru: BTreeSet::new(),
+            lru_keys: HashMap::new(),
+            evicted: HashSet::new(),
+            counters: EvictionCounters::default(),
+            epoch: 0.0,
+            config,
+        }
+    }
+
+    /// load the paged octree from the cell store if present, otherwise populate it from the star catalogues.
+    /// with `cache`, the populated octree is saved to `cache::CACHE_PATH` and reused until a mod or `config` changes
+    pub fn new(cache: bool, config: TreeConfig) -> Result<Universe, UniverseError> {
+        let store = CellStore::new(store::CELL_DIR);
+
+        if let Some(root) = store.load(Sector::ID_ROOT)? {
+            log::info!("loaded paged octree from {:?}", store::CELL_DIR);
+            let config = Self::paged_config(&store, config)?;
+            return Ok(Universe::with_root(root, store, config));
+        }
+
+        Ok(Self::from_catalogues(cache, config)?)
+    }
+
+    /// config to load a paged octree with, its cells are split as they were when paged out, whatever `config` says
+    fn paged_config(store: &CellStore, config: TreeConfig) -> Result<TreeConfig, StoreError> {
+        let Some(paged) = store.load_config()? else {
+            log::warn!("paged octree doesn't record the config it was built with, assuming {config:?}");
+            return Ok(config);
+        };
+
+        let paged = TreeConfig { mesh_combine_threshold: config.mesh_combine_threshold, ..paged };
+        if paged != config {
+            log::warn!("paged octree was built with leaf capacity {} and max depth {}, using those instead", paged.leaf_capacity, paged.max_depth);
+        }
+
+        Ok(paged)
+    }
+
+    /// populate the octree from the star catalogues, ignoring any paged octree in the cell store.
+    /// with `cache`, the octree is loaded from the octree cache if it was built from the same mods and config, and saved to it if not
+    pub fn from_catalogues(cache: bool, config: TreeConfig) -> Result<Universe, ModError> {
         let colour_index = 3.4;
 
         let brightness = 2.512f64.powf(-54.0);
         let temperature = 4600.0f64*(1.0/(0.92*colour_index + 1.7) + 1.0/(0.92*colour_index + 0.62));
-        let colour = temperature_rgb(temperature) * brightness * 1.0e36;
+        let luminosity = Spectrum::black_body(temperature, brightness * 1.0e36);
 
         let mod_fs = ModFs::new()?;
-        
-        let mut universe = Universe {
-            root: Cell::new(Vec3F::ONE * -Self::REGION_SIZE / 2.0, Vec3F::ONE * Self::REGION_SIZE / 2.0, colour),
+        // each catalogue is read once, to hash it and then to decode it if the cache is missed
+        let catalogues = mod_fs.read_dir("catalogues/stars")?.into_iter()
+            .map(|path| Ok((mod_fs.read(&path)?, path)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // the catalogues are only hashed when the cache is used
+        let cache = if cache {
+            let mut key = CacheKey::default();
+            mod_fs.hash_mods(&mut key);
+            for (contents, _) in &catalogues {
+                key.write_usize(contents.len());
+                key.write(contents);
+            }
+            // `mesh_combine_threshold` only changes how the octree is drawn
+            (config.leaf_capacity, config.max_depth).hash(&mut key);
+            Some((OctreeCache::new(cache::CACHE_PATH), key.finish()))
+        } else {
+            None
         };
 
+        if let Some((cache, key)) = &cache {
+            match cache.load(*key) {
+                Ok(Some(root)) => {
+                    log::info!("loaded octree from cache {:?}", cache.path());
+                    return Ok(Universe::with_root(root, CellStore::new(store::CELL_DIR), config));
+                },
+                Ok(None) => {},
+                Err(err) => log::error!("failed to load octree cache {:?}, rebuilding: {err}", cache.path()),
+            }
+        }
+        
         let mut stars = Vec::new();
 
         log::info!("loading star catalogues...");
-        for path in mod_fs.read_dir("catalogues/stars")? {
-            let catalogue = mod_fs.decompress_bin::<StarCatalogue>(&path)?;
+        for (contents, path) in catalogues {
+            let catalogue = StarCatalogue::decode(fs::decompress(&contents))?.ok_or_else(|| ModError::CatalogueFormat(path.clone()))?;
             log::info!("loaded star catalogue {:?} ({} stars)", path.file_name().expect("attempted to open a non-file star catalogue"), catalogue.stars.len());
             stars.extend(catalogue.stars);
         }
@@ -86,45 +370,205 @@ impl Universe {
 
         log::info!("loaded {num_stars} stars");
 
-        log::info!("populating octree with {num_stars} stars");
-        for (i, star) in stars.into_iter().enumerate() {
+        let bodies = stars.into_iter().map(|star| {
             let temperature = ci_temperature(star.colour_index);
             let brightness = abs_mag_brightness(star.abs_mag);
-            let colour = temperature_rgb(temperature) * brightness;
-
-            // if star.name == "Gacrux" || star.name == "Acrux" || star.name == "Mimosa" || star.name == "Imai" {
-            //     colour *= glam::DVec3::Y;
-            // }
-
-            if i % (num_stars / 4) == 0 || i == num_stars - 1 {
-                log::info!("populating octree with {num_stars} stars: {:.1}%", i as f32 / num_stars as f32 * 100.0);
-            } else if i % (num_stars / 10) == 0 {
-                log::debug!("populating octree with {num_stars} stars: {:.1}%", i as f32 / num_stars as f32 * 100.0);
-            } else if i % (num_stars / 25) == 0 {
-                log::trace!("populating octree with {num_stars} stars: {:.1}%", i as f32 / num_stars as f32 * 100.0);
+
+            Star {
+                position: star.pos.into(),
+                radius: FP128::from_num(stellar_radius(temperature, star.abs_mag)),
+                spectrum: Spectrum::black_body(temperature, brightness),
+                velocity: star.vel.into(),
             }
+        }).collect();
 
-            universe.root.add_body(Body {
-                position: star.pos,
-                colour,
-            });
-        }
+        log::info!("populating octree with {num_stars} stars");
+        let universe = Universe::with_root(
+            Cell::from_bodies(Self::region(), luminosity, bodies, config),
+            CellStore::new(store::CELL_DIR),
+            config,
+        );
         log::info!("populated octree");
 
+        if let Some((cache, key)) = &cache {
+            match cache.save(*key, &universe.root) {
+                Ok(()) => log::info!("saved octree cache {:?}", cache.path()),
+                Err(err) => log::error!("failed to save octree cache {:?}: {err}", cache.path()),
+            }
+        }
+
         Ok(universe)
     }
 
-    pub fn all_visible_from(&mut self, point: Vec3F, fovy: f32, screen_height: u32) -> Vec<CellVisibility> {
-        self.root.all_visible_from(point, fovy, screen_height, &mut generate_cell)
+    /// changes to the visible set since the last call, the first call adds everything visible.
+    /// afterwards, cells are evicted until the octree fits in the memory budget
+    pub fn all_visible_from(&mut self, view: &View) -> VisibilityDelta {
+        self.visit += 1;
+
+        let loader = Loader::new(&self.scratch, &self.store, self.config, self.visit, &mut self.evicted);
+        let mut candidates = vec![];
+        let visible = self.root.all_visible_from(view, self.visit, &|id, bounds, luminosity| loader.load(id, bounds, luminosity), &mut candidates);
+        let loads = loader.into();
+        self.finish_pass(loads, candidates);
+
+        VisibilityDelta::update(&mut self.visible, visible)
+    }
+
+    /// bodies within `tolerance` radians of a ray, e.g. from `render::Camera::pick_ray`
+    #[allow(unused)]
+    pub fn pick(&mut self, origin: Vec3F, direction: glam::DVec3, tolerance: f64, order: PickOrder) -> Vec<Pick> {
+        let loader = Loader::new(&self.scratch, &self.store, self.config, self.visit, &mut self.evicted);
+        let picks = self.root.pick(origin, direction, tolerance, order, &mut |id, bounds, luminosity| loader.load(id, bounds, luminosity));
+        let loads = loader.into();
+        self.finish_pass(loads, vec![]);
+        picks
+    }
+
+    /// seconds since J2000 that bodies have been moved to, see `Universe::set_epoch`
+    #[allow(unused)]
+    pub fn epoch(&self) -> f64 {
+        self.epoch
+    }
+
+    /// move every body along its velocity to `epoch` seconds since J2000, returns the number of bodies moved.
+    /// every paged out cell is loaded first, which may briefly go over the memory budget. cells never paged out
+    /// only hold generated stars, which are at rest, so are left unloaded
+    pub fn set_epoch(&mut self, epoch: f64) -> usize {
+        // not reached by any visibility query, so evicted first
+        let loader = Loader::new(&self.scratch, &self.store, self.config, 0, &mut self.evicted);
+        let (scratch, store) = (&self.scratch, &self.store);
+        self.root.load_where(&|id| scratch.contains(id) || store.contains(id), &mut |id, bounds, luminosity| loader.load(id, bounds, luminosity));
+        let moved = self.root.propagate(epoch - self.epoch, &mut |id, bounds, luminosity| loader.load(id, bounds, luminosity));
+        self.epoch = epoch;
+
+        // bodies moved between cells, which may have been split or collapsed, so the loaded memory is counted afresh
+        let loads = Loads { memory: 0, ..loader.into() };
+        self.memory = self.root.memory();
+        self.finish_pass(loads, vec![]);
+
+        moved
+    }
+
+    /// limit the estimated memory used by the loaded octree, in bytes. `None` never evicts anything
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    pub fn counters(&self) -> EvictionCounters {
+        self.counters
+    }
+
+    /// count the cells loaded by a pass over the octree and settle any that were generated, then move them and
+    /// the `candidates` the pass reached to their place in the eviction order and evict down to the memory budget
+    fn finish_pass(&mut self, loads: Loads, candidates: Vec<EvictionCandidate>) {
+        self.counters.reloads += loads.reloads;
+        self.memory += loads.memory;
+        if loads.generated > 0 {
+            self.root.settle();
+        }
+
+        for candidate in loads.loaded.into_iter().chain(candidates) {
+            self.touch(candidate);
+        }
+        self.evict_to_budget();
+    }
+
+    /// move a cell to its place in the eviction order
+    fn touch(&mut self, EvictionCandidate { id, depth, visited }: EvictionCandidate) {
+        if let Some((old_visited, old_depth)) = self.lru_keys.insert(id, (visited, depth)) {
+            self.lru.remove(&(old_visited, old_depth, id));
+        }
+        self.lru.insert((visited, depth, id));
+    }
+
+    /// evict least recently visited cells, shallowest first, until the octree fits in the memory budget.
+    /// cells reached by the latest visibility query are never evicted
+    fn evict_to_budget(&mut self) {
+        let Some(budget) = self.memory_budget else { return; };
+
+        if self.memory <= budget {
+            return;
+        }
+
+        let scratch = &self.scratch;
+        while self.memory > budget {
+            let Some(&(visited, depth, id)) = self.lru.first() else { break; };
+            if visited >= self.visit {
+                break;
+            }
+            self.lru.remove(&(visited, depth, id));
+            self.lru_keys.remove(&id);
+
+            match self.root.evict(id, &mut |cell| scratch.save(cell)) {
+                Ok(Some(freed)) => {
+                    self.memory = self.memory.saturating_sub(freed);
+                    self.evicted.insert(id);
+                    self.counters.evictions += 1;
+                },
+                // already evicted along with an ancestor, or no longer a separate cell
+                Ok(None) => {},
+                Err(err) => log::error!("failed to page out cell {id} for eviction: {err}"),
+            }
+        }
+
+        log::debug!("evicted cells down to {} bytes, {:?}", self.memory, self.counters);
+    }
+
+    /// check the loaded part of the octree for broken invariants
+    pub fn validate(&self) -> Vec<Violation> {
+        self.root.validate()
+    }
+
+    /// shape and size of the loaded part of the octree
+    pub fn stats(&self) -> TreeStats {
+        self.root.stats()
+    }
+
+    /// split the octree into cells of `stride` levels and write them to the cell store, including the root
+    pub fn page_out(&mut self, stride: usize) -> Result<(), StoreError> {
+        let store = &self.store;
+        self.root.page_out(stride, &mut |cell| store.save(&cell))?;
+        store.save(&self.root)?;
+        store.save_config(self.config)
+    }
+}
+
+impl Drop for Universe {
+    fn drop(&mut self) {
+        if let Err(err) = self.scratch.clear() {
+            log::error!("failed to delete evicted cells: {err}");
+        }
+    }
+}
+
+/// catalogue position columns, either `x`, `y`, `z` or `lon`, `lat` (degrees), `dist`, in parsecs
+struct CsvPosition {
+    x: Option<f64>,
+    y: Option<f64>,
+    z: Option<f64>,
+    lon: Option<f64>,
+    lat: Option<f64>,
+    dist: Option<f64>,
+}
+
+impl CsvPosition {
+    fn position(self, frame: Frame) -> csv::Result<Metres<Vec3F>> {
+        match self {
+            CsvPosition { x: Some(x), y: Some(y), z: Some(z), .. } => Ok(Parsecs(frame.to_engine(glam::dvec3(x, y, z))).into()),
+            CsvPosition { lon: Some(lon), lat: Some(lat), dist: Some(dist), .. } => Ok(frame.position(lon, lat, Parsecs(dist).into())),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "record needs either x, y, z or lon, lat, dist").into()),
+        }
     }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct StarCatalogueRecord {
     pub name: String,
-    pub pos: Vec3F,
+    pub pos: Metres<Vec3F>,
     pub colour_index: f64,
     pub abs_mag: f64,
+    /// space velocity
+    pub vel: MetresPerSecond<glam::DVec3>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -132,16 +576,72 @@ pub struct StarCatalogue {
     pub stars: Vec<StarCatalogueRecord>,
 }
 
+/// changes whenever the layout of an encoded catalogue does, catalogues encoded by older engines are rejected
+const CATALOGUE_FORMAT: u32 = 1;
+
+/// written before each encoded catalogue, checked before it is read
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct CatalogueHeader {
+    /// catalogues encoded before there was a header start with their number of stars instead
+    magic: [u8; 8],
+    format: u32,
+    /// metres in a parsec, catalogue positions are converted to metres with it when encoded
+    parsec: f64,
+}
+
+impl CatalogueHeader {
+    const CURRENT: CatalogueHeader = CatalogueHeader { magic: *b"UECATLG\0", format: CATALOGUE_FORMAT, parsec: Parsecs::METRES };
+}
+
 impl StarCatalogue {
-    pub fn from_csv<T: io::Read>(mut reader: csv::Reader<T>) -> csv::Result<StarCatalogue> {
+    /// write the catalogue in the current catalogue format
+    pub fn encode(&self, mut writer: impl io::Write) -> bincode::Result<()> {
+        bincode::serialize_into(&mut writer, &CatalogueHeader::CURRENT)?;
+        bincode::serialize_into(writer, self)
+    }
+
+    /// read a catalogue written by `encode`, returns `None` if it was encoded in another format
+    pub fn decode(mut reader: impl io::Read) -> bincode::Result<Option<StarCatalogue>> {
+        let header = match bincode::deserialize_from::<_, CatalogueHeader>(&mut reader) {
+            Ok(header) => Some(header),
+            // too short for a header is another format too
+            Err(err) if matches!(&*err, bincode::ErrorKind::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof) => None,
+            Err(err) => return Err(err),
+        };
+        if header != Some(CatalogueHeader::CURRENT) {
+            return Ok(None);
+        }
+
+        Ok(Some(bincode::deserialize_from(reader)?))
+    }
+
+    /// positions and velocities are read in `frame`
+    pub fn from_csv<T: io::Read>(mut reader: csv::Reader<T>, frame: Frame) -> csv::Result<StarCatalogue> {
         #[derive(serde::Deserialize)]
         struct Record {
             name: String,
-            x: f64,
-            y: f64,
-            z: f64,
+            // cartesian, or else spherical in degrees, both in parsecs
+            #[serde(default)]
+            x: Option<f64>,
+            #[serde(default)]
+            y: Option<f64>,
+            #[serde(default)]
+            z: Option<f64>,
+            #[serde(default)]
+            lon: Option<f64>,
+            #[serde(default)]
+            lat: Option<f64>,
+            #[serde(default)]
+            dist: Option<f64>,
             colour_index: f64,
             abs_mag: f64,
+            // km/s, catalogues without velocities leave the stars where they are
+            #[serde(default)]
+            vx: Option<f64>,
+            #[serde(default)]
+            vy: Option<f64>,
+            #[serde(default)]
+            vz: Option<f64>,
         }
         
         let mut catalogue = StarCatalogue {
@@ -154,15 +654,22 @@ impl StarCatalogue {
                 x,
                 y,
                 z,
+                lon,
+                lat,
+                dist,
                 colour_index,
                 abs_mag,
+                vx,
+                vy,
+                vz,
             } = record?;
 
             catalogue.stars.push(StarCatalogueRecord {
                 name,
-                pos: Vec3F::from_dvec3(glam::dvec3(x, y, z) * 3.086e+16), // convert from parsecs to m
+                pos: CsvPosition { x, y, z, lon, lat, dist }.position(frame)?,
                 colour_index,
                 abs_mag,
+                vel: MetresPerSecond(frame.to_engine(glam::dvec3(vx.unwrap_or(0.0), vy.unwrap_or(0.0), vz.unwrap_or(0.0))) * 1000.0), // convert from km/s to m/s
             });
         }
 
@@ -173,11 +680,11 @@ impl StarCatalogue {
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct GalaxyCatalogueRecord {
     pub name: String,
-    pub pos: Vec3F,
+    pub pos: Metres<Vec3F>,
     pub normal: glam::Vec3,
     pub tangent: glam::Vec3,
-    pub diameter: f64,
-    pub thickness_stddev: f64,
+    pub diameter: Metres,
+    pub thickness_stddev: Metres,
     pub abs_mag: f64,
     pub colour: String,
     pub height: String,
@@ -189,13 +696,35 @@ pub struct GalaxyCatalogue {
 }
 
 impl GalaxyCatalogue {
-    pub fn from_csv<T: io::Read>(mut reader: csv::Reader<T>) -> csv::Result<GalaxyCatalogue> {
+    /// the galaxies as octree bodies. the colour maps aren't sampled yet, so every galaxy is the colour of the sun
+    pub fn bodies(&self) -> impl Iterator<Item = Galaxy> + '_ {
+        // B-V colour index of the sun
+        let temperature = ci_temperature(0.65);
+        self.galaxies.iter().map(move |galaxy| Galaxy {
+            position: galaxy.pos.into(),
+            diameter: galaxy.diameter.into(),
+            spectrum: Spectrum::black_body(temperature, abs_mag_brightness(galaxy.abs_mag)),
+        })
+    }
+
+    /// positions, normals and tangents are read in `frame`
+    pub fn from_csv<T: io::Read>(mut reader: csv::Reader<T>, frame: Frame) -> csv::Result<GalaxyCatalogue> {
         #[derive(serde::Deserialize)]
         struct Record {
             name: String,
-            x: f64,
-            y: f64,
-            z: f64,
+            // cartesian, or else spherical in degrees, both in parsecs
+            #[serde(default)]
+            x: Option<f64>,
+            #[serde(default)]
+            y: Option<f64>,
+            #[serde(default)]
+            z: Option<f64>,
+            #[serde(default)]
+            lon: Option<f64>,
+            #[serde(default)]
+            lat: Option<f64>,
+            #[serde(default)]
+            dist: Option<f64>,
             nx: f32,
             ny: f32,
             nz: f32,
@@ -219,6 +748,9 @@ impl GalaxyCatalogue {
                 x,
                 y,
                 z,
+                lon,
+                lat,
+                dist,
                 nx,
                 ny,
                 nz,
@@ -234,11 +766,11 @@ impl GalaxyCatalogue {
 
             catalogue.galaxies.push(GalaxyCatalogueRecord {
                 name,
-                pos: Vec3F::from_dvec3(glam::dvec3(x, y, z)),
-                normal: glam::vec3(nx, ny, nz),
-                tangent: glam::vec3(tx, ty, tz),
-                diameter,
-                thickness_stddev,
+                pos: CsvPosition { x, y, z, lon, lat, dist }.position(frame)?,
+                normal: frame.to_engine(glam::dvec3(nx as f64, ny as f64, nz as f64)).as_vec3(),
+                tangent: frame.to_engine(glam::dvec3(tx as f64, ty as f64, tz as f64)).as_vec3(),
+                diameter: Parsecs(diameter).into(),
+                thickness_stddev: Parsecs(thickness_stddev).into(),
                 abs_mag,
                 colour,
                 height,
@@ -248,3 +780,263 @@ impl GalaxyCatalogue {
         Ok(catalogue)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tree::random_stars;
+
+    #[test]
+    fn visibility_delta() {
+        let cell = |id: u128, colour: f64| CellVisibility {
+            id,
+            centre: Vec3F::ZERO,
+            depth: 1,
+            bodies: vec![crate::tree::PointLight { position: Vec3F::ONE, diameter: FP128::ONE, spectrum: Spectrum::splat(colour), kind: Some(crate::tree::BodyKind::Star), resolved: false }],
+        };
+        let mut previous = HashMap::new();
+
+        let delta = VisibilityDelta::update(&mut previous, vec![cell(8, 1.0), cell(9, 1.0)]);
+        assert_eq!(delta.added.len(), 2);
+        assert!(delta.changed.is_empty() && delta.removed.is_empty());
+
+        let delta = VisibilityDelta::update(&mut previous, vec![cell(8, 1.0), cell(9, 1.0)]);
+        assert!(delta.added.is_empty() && delta.changed.is_empty() && delta.removed.is_empty());
+
+        let delta = VisibilityDelta::update(&mut previous, vec![cell(9, 2.0), cell(10, 1.0)]);
+        assert_eq!(delta.added.iter().map(|c| c.id).collect::<Vec<_>>(), vec![10]);
+        assert_eq!(delta.changed.iter().map(|c| c.id).collect::<Vec<_>>(), vec![9]);
+        assert_eq!(delta.removed, vec![8]);
+        assert_eq!(previous.len(), 2);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let dir = std::env::temp_dir().join(format!("universe-engine-memory-budget-{}", std::process::id()));
+
+        let bodies = random_stars(4, 1024, 64.0);
+        let root = Cell::from_bodies(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO, bodies, TreeConfig::DEFAULT);
+        let memory = root.stats().memory;
+
+        let mut universe = Universe::with_root(root, CellStore::new(&dir), TreeConfig::DEFAULT);
+        universe.set_memory_budget(Some(memory / 4));
+        assert_eq!(universe.set_epoch(JULIAN_YEAR), 0);
+
+        let mut view = View { position: Vec3F::ZERO, rotation: glam::Quat::IDENTITY, fovy: 1.5, aspect: 1.0, screen_height: 1080, min_flux: 0.0 };
+        universe.all_visible_from(&view);
+        let visible = universe.visible.clone();
+        let counters = universe.counters();
+        assert!(counters.evictions > 0);
+        assert_eq!(counters.reloads, 0);
+
+        // everything visible is kept
+        let delta = universe.all_visible_from(&view);
+        assert!(delta.added.is_empty() && delta.changed.is_empty() && delta.removed.is_empty());
+
+        // picking behind the camera loads cells again, which are counted and evicted like any others
+        let (counters, lru) = (universe.counters(), universe.lru.len());
+        universe.pick(Vec3F::ZERO, glam::DVec3::Z, 0.5, PickOrder::Distance);
+        assert!(universe.counters().reloads > counters.reloads);
+        assert!(universe.lru.len() > lru);
+        assert_eq!(universe.memory, universe.root.memory());
+        universe.all_visible_from(&view);
+        assert!(universe.counters().evictions > counters.evictions);
+        assert_eq!(universe.memory, universe.root.memory());
+
+        // look the other way and back, the same cells are loaded again
+        view.rotation = glam::Quat::from_rotation_y(std::f32::consts::PI);
+        universe.all_visible_from(&view);
+        view.rotation = glam::Quat::IDENTITY;
+        universe.all_visible_from(&view);
+        assert!(universe.counters().reloads > 0);
+        assert_eq!(universe.visible, visible);
+        assert_eq!(universe.validate(), vec![]);
+
+        // evicted cells are loaded again to move their stars, then evicted down to the budget
+        let (counters, loaded) = (universe.counters(), universe.memory);
+        universe.set_epoch(0.0);
+        assert_eq!(universe.epoch(), 0.0);
+        assert!(universe.counters().reloads > counters.reloads);
+        assert!(universe.memory <= loaded);
+        assert_eq!(universe.validate(), vec![]);
+
+        // evicted cells go to a scratch store, which is deleted along with the universe
+        assert!(!dir.exists());
+        let scratch_dir = universe.scratch.dir().to_owned();
+        assert!(scratch_dir.exists());
+        drop(universe);
+        assert!(!scratch_dir.exists());
+    }
+
+    #[test]
+    fn paged_config() {
+        let dir = std::env::temp_dir().join(format!("universe-engine-paged-config-{}", std::process::id()));
+        let config = TreeConfig { leaf_capacity: 8, max_depth: 4, ..TreeConfig::DEFAULT };
+
+        let bodies = random_stars(5, 1024, 64.0);
+        let root = Cell::from_bodies(Aabb::new(Vec3F::ONE * -64.0, Vec3F::ONE * 64.0), Spectrum::ZERO, bodies, config);
+        let store = CellStore::new(&dir);
+        assert_eq!(Universe::paged_config(&store, TreeConfig::DEFAULT).unwrap(), TreeConfig::DEFAULT);
+        Universe::with_root(root, CellStore::new(&dir), config).page_out(2).unwrap();
+
+        // loaded with the default config, the pages are still split as they were
+        let mesh_combine_threshold = 16;
+        let loaded = Universe::paged_config(&store, TreeConfig { mesh_combine_threshold, ..TreeConfig::DEFAULT }).unwrap();
+        assert_eq!(loaded, TreeConfig { mesh_combine_threshold, ..config });
+        let view = View { position: Vec3F::ZERO, rotation: glam::Quat::IDENTITY, fovy: 1.5, aspect: 1.0, screen_height: 1080, min_flux: 0.0 };
+        let mut universe = Universe::with_root(store.load(Sector::ID_ROOT).unwrap().unwrap(), CellStore::new(&dir), loaded);
+        universe.all_visible_from(&view);
+        assert!(universe.stats().bodies > 100);
+        assert_eq!(universe.validate(), vec![]);
+
+        // which the default config would find overfull
+        let mut universe = Universe::with_root(store.load(Sector::ID_ROOT).unwrap().unwrap(), CellStore::new(&dir), TreeConfig::DEFAULT);
+        universe.all_visible_from(&view);
+        assert!(!universe.validate().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn paged_epoch() {
+        let dir = std::env::temp_dir().join(format!("universe-engine-paged-epoch-{}", std::process::id()));
+        let config = TreeConfig { leaf_capacity: 8, max_depth: 4, ..TreeConfig::DEFAULT };
+
+        // every other star moves a metre a year along x
+        let mut bodies = random_stars(6, 1024, 64.0);
+        for body in bodies.iter_mut().step_by(2) {
+            body.velocity = glam::DVec3::X / JULIAN_YEAR;
+        }
+        let root = Cell::from_bodies(Aabb::new(Vec3F::ONE * -128.0, Vec3F::ONE * 128.0), Spectrum::ZERO, bodies.clone(), config);
+        let store = CellStore::new(&dir);
+        Universe::with_root(root, CellStore::new(&dir), config).page_out(2).unwrap();
+
+        // the pages below the root are loaded to move the stars in them
+        let mut universe = Universe::with_root(store.load(Sector::ID_ROOT).unwrap().unwrap(), CellStore::new(&dir), config);
+        assert!(universe.stats().unloaded > 0);
+        assert_eq!(universe.set_epoch(JULIAN_YEAR * 10.0), 512);
+        assert_eq!(universe.stats().bodies, 1024);
+        assert_eq!(universe.validate(), vec![]);
+        for body in &bodies {
+            let expected = body.position + Vec3F::from_dvec3(body.velocity * JULIAN_YEAR * 10.0);
+            let nearest = universe.root.nearest(expected, 1);
+            assert!(nearest[0].1 < FP128::from_num(1e-6));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stellar_radius() {
+        let sun = super::stellar_radius(ci_temperature(0.65), SUN_ABS_MAG);
+        assert!((sun / 6.957e8 - 1.0).abs() < 0.1, "{sun}");
+
+        // as bright but hotter, so smaller
+        assert!(super::stellar_radius(ci_temperature(-0.2), SUN_ABS_MAG) < sun);
+        // as hot but brighter, so larger
+        assert!(super::stellar_radius(ci_temperature(0.65), SUN_ABS_MAG - 5.0) > sun * 9.0);
+    }
+
+    #[test]
+    fn generate_cell() {
+        let id = Sector::ID_ROOT * 64 + 0o52;
+        let bounds = Aabb::new(Vec3F::ONE * -1.0e15, Vec3F::ONE * 1.0e15);
+        let budget = Spectrum::black_body(5000.0, abs_mag_brightness(SUN_ABS_MAG) * 100.0);
+
+        let cell = super::generate_cell(id, bounds, budget, TreeConfig::DEFAULT);
+        assert_eq!(cell.sector().id(), id);
+        assert_eq!(cell.validate(), vec![]);
+
+        // the stars spend the whole budget in at least one bin, and never more than it in any
+        let bodies = cell.within_box(&bounds);
+        let spent = bodies.iter().map(|b| b.spectrum).sum::<Spectrum>();
+        let ratios = spent.bins().into_iter().zip(budget.bins()).map(|(s, b)| s / b).collect::<Vec<_>>();
+        assert!(ratios.iter().all(|r| *r < 1.0 + 1e-9));
+        assert!(ratios.iter().any(|r| (r - 1.0).abs() < 1e-9));
+        assert!(bodies.len() > 1 && bodies.len() <= MAX_GENERATED_BODIES);
+        // stars keep their own colours rather than taking on the budget's
+        let colour = |s: Spectrum| s / s.total();
+        assert!(bodies.iter().any(|b| (colour(b.spectrum) - colour(budget)).abs().max_element() > 1e-3));
+
+        // regenerating gives exactly the same cell, another id doesn't
+        let again = super::generate_cell(id, bounds, budget, TreeConfig::DEFAULT);
+        assert_eq!(bincode::serialize(&cell).unwrap(), bincode::serialize(&again).unwrap());
+        let other = super::generate_cell(id + 1, bounds, budget, TreeConfig::DEFAULT);
+        assert_ne!(bincode::serialize(&cell).unwrap(), bincode::serialize(&other).unwrap());
+
+        let empty = super::generate_cell(id, bounds, Spectrum::ZERO, TreeConfig::DEFAULT);
+        assert!(empty.within_box(&bounds).is_empty());
+
+        // a budget too big for one cell is left to its children, unless it is already as deep as the tree goes
+        let huge = budget * 1.0e3;
+        let split = super::generate_cell(id, bounds, huge, TreeConfig::DEFAULT);
+        assert!(split.within_box(&bounds).is_empty());
+        assert_eq!(split.stats().unloaded, 8);
+        let capped = super::generate_cell(id, bounds, huge, TreeConfig { max_depth: 2, ..TreeConfig::DEFAULT });
+        assert_eq!(capped.within_box(&bounds).len(), MAX_GENERATED_BODIES);
+    }
+
+    #[test]
+    fn generated_tree() {
+        let dir = std::env::temp_dir().join(format!("universe-engine-generated-tree-{}", std::process::id()));
+
+        // enough light for tens of thousands of stars, generated two levels down
+        let bounds = Aabb::new(Vec3F::ONE * -1.0e15, Vec3F::ONE * 1.0e15);
+        let budget = Spectrum::black_body(5000.0, abs_mag_brightness(SUN_ABS_MAG) * 2.0e4);
+        let root = Cell::with_unloaded_children(Sector::ID_ROOT, bounds, budget, TreeConfig::DEFAULT);
+        let mut universe = Universe::with_root(root, CellStore::new(&dir), TreeConfig::DEFAULT);
+
+        let view = View { position: Vec3F::ZERO, rotation: glam::Quat::IDENTITY, fovy: 1.5, aspect: 1.0, screen_height: 1080, min_flux: 0.0 };
+        assert!(!universe.all_visible_from(&view).added.is_empty());
+        assert!(universe.stats().bodies > MAX_GENERATED_BODIES);
+
+        // generated cells add up to what their parents recorded, bodies and all
+        assert_eq!(universe.validate(), vec![]);
+        universe.pick(Vec3F::ZERO, glam::DVec3::NEG_Z, 0.5, PickOrder::Distance);
+        assert_eq!(universe.validate(), vec![]);
+    }
+
+    #[test]
+    fn star_csv() {
+        let csv = "name,x,y,z,lon,lat,dist,colour_index,abs_mag,vx,vy,vz\n\
+            cartesian,0,1,0,,,,0.6,4.8,1,0,0\n\
+            spherical,,,,90,0,1,0.6,4.8,,,\n";
+        let catalogue = StarCatalogue::from_csv(csv::Reader::from_reader(csv.as_bytes()), Frame::Ecliptic).unwrap();
+
+        // both are a parsec towards ecliptic longitude 90, which is above the equator
+        let expected = Frame::Ecliptic.position(90.0, 0.0, Parsecs(1.0).into());
+        for star in &catalogue.stars {
+            let pos = Vec3F::from(star.pos);
+            assert!(pos.distance(expected.into()) < FP128::from_num(1e3), "{}", star.name);
+            assert!(pos.z > FP128::ZERO);
+        }
+        assert_eq!(catalogue.stars[0].vel, MetresPerSecond(glam::dvec3(1000.0, 0.0, 0.0)));
+        assert_eq!(catalogue.stars[1].vel, MetresPerSecond(glam::DVec3::ZERO));
+
+        let missing = "name,x,y,colour_index,abs_mag\nbroken,1,2,0.6,4.8\n";
+        assert!(StarCatalogue::from_csv(csv::Reader::from_reader(missing.as_bytes()), Frame::Equatorial).is_err());
+    }
+
+    #[test]
+    fn catalogue_format() {
+        let csv = "name,x,y,z,lon,lat,dist,colour_index,abs_mag,vx,vy,vz\nsun,0,0,0,,,,0.65,4.83,,,\n";
+        let catalogue = StarCatalogue::from_csv(csv::Reader::from_reader(csv.as_bytes()), Frame::Equatorial).unwrap();
+
+        let mut encoded = vec![];
+        catalogue.encode(&mut encoded).unwrap();
+        let decoded = StarCatalogue::decode(encoded.as_slice()).unwrap().unwrap();
+        assert_eq!(decoded.stars[0].name, "sun");
+        assert_eq!(decoded.stars[0].abs_mag, 4.83);
+
+        // catalogues encoded before there was a header, or by a future engine, are rejected rather than misread
+        assert!(StarCatalogue::decode(bincode::serialize(&catalogue).unwrap().as_slice()).unwrap().is_none());
+        assert!(StarCatalogue::decode(&[][..]).unwrap().is_none());
+        let future = bincode::serialize(&CatalogueHeader { format: CATALOGUE_FORMAT + 1, ..CatalogueHeader::CURRENT }).unwrap();
+        assert!(StarCatalogue::decode(future.as_slice()).unwrap().is_none());
+        // as are catalogues whose positions were converted with another length of parsec
+        let parsec = bincode::serialize(&CatalogueHeader { parsec: 3.086e16, ..CatalogueHeader::CURRENT }).unwrap();
+        assert!(StarCatalogue::decode([parsec, bincode::serialize(&catalogue).unwrap()].concat().as_slice()).unwrap().is_none());
+    }
+}
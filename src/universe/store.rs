@@ -0,0 +1,181 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{self, AtomicUsize},
+};
+
+use flate2::{bufread::GzDecoder, write::GzEncoder};
+
+use crate::{tree::{Body, Cell, TreeConfig}, units::Parsecs};
+
+pub const CELL_DIR: &str = "data/cells";
+/// config the stored cells were split with, see `CellStore::save_config`
+const CONFIG_FILE: &str = "config.bin";
+
+/// changes whenever the layout of a stored cell does, cells stored by older engines are treated as never paged out
+const CELL_FORMAT: u32 = 2;
+
+/// written before each cell, checked before it is read
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct CellHeader {
+    /// cells stored before there was a header start with their sector id instead
+    magic: [u8; 8],
+    format: u32,
+    /// metres in a parsec when the cell's catalogues were encoded, see `CatalogueHeader`
+    parsec: f64,
+}
+
+impl CellHeader {
+    const CURRENT: CellHeader = CellHeader { magic: *b"UECELL\0\0", format: CELL_FORMAT, parsec: Parsecs::METRES };
+}
+
+/// new directory for cells evicted from memory, unique to this process so the shipped cells are never overwritten
+pub fn scratch_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!("universe-engine-cells-{}-{}", std::process::id(), NEXT.fetch_add(1, atomic::Ordering::Relaxed)))
+}
+
+/// directory of paged octree subtrees, one compressed file per sector id
+pub struct CellStore {
+    dir: PathBuf,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("io error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("parse error: {0}")]
+    BinParseError(#[from] bincode::Error),
+}
+
+impl CellStore {
+    pub fn new(dir: impl Into<PathBuf>) -> CellStore {
+        CellStore { dir: dir.into() }
+    }
+
+    #[cfg(test)]
+    pub fn dir(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    fn path(&self, id: u128) -> PathBuf {
+        self.dir.join(format!("cell_{id}.bin.gz"))
+    }
+
+    /// whether a cell with the given sector id has been paged out, in any format
+    pub fn contains(&self, id: u128) -> bool {
+        self.path(id).exists()
+    }
+
+    /// load the cell with the given sector id, returns `None` if it has not been paged out or was stored in another format
+    pub fn load<B: Body>(&self, id: u128) -> Result<Option<Cell<B>>, StoreError> {
+        let path = self.path(id);
+
+        log::trace!("loading cell {path:?}");
+
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        // bincode reads a few bytes at a time, which is slow straight from the decoder
+        let mut reader = io::BufReader::new(GzDecoder::new(io::BufReader::new(file)));
+
+        let header = match bincode::deserialize_from::<_, CellHeader>(&mut reader) {
+            Ok(header) => Some(header),
+            // too short for a header is another format too
+            Err(err) if matches!(&*err, bincode::ErrorKind::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof) => None,
+            Err(err) => return Err(err.into()),
+        };
+        if header != Some(CellHeader::CURRENT) {
+            log::warn!("cell {path:?} was stored in another format, ignoring it");
+            return Ok(None);
+        }
+
+        Ok(Some(bincode::deserialize_from(reader)?))
+    }
+
+    /// write a cell to the store, replacing any previous cell with the same sector id
+    pub fn save<B: Body>(&self, cell: &Cell<B>) -> Result<(), StoreError> {
+        fs::create_dir_all(&self.dir)?;
+
+        let path = self.path(cell.sector().id());
+
+        log::trace!("saving cell {path:?}");
+
+        let mut writer = GzEncoder::new(io::BufWriter::new(fs::File::create(path)?), Default::default());
+        {
+            // bincode writes a few bytes at a time, which is slow straight into the encoder
+            let mut buffered = io::BufWriter::new(&mut writer);
+            bincode::serialize_into(&mut buffered, &CellHeader::CURRENT)?;
+            bincode::serialize_into(&mut buffered, cell)?;
+            buffered.flush()?;
+        }
+        writer.finish()?.flush()?;
+
+        Ok(())
+    }
+
+    /// config the stored cells were split with, `None` if it was never saved
+    pub fn load_config(&self) -> Result<Option<TreeConfig>, StoreError> {
+        match fs::read(self.dir.join(CONFIG_FILE)) {
+            Ok(config) => Ok(Some(bincode::deserialize(&config)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// record the config the stored cells were split with, so they can be loaded into a tree with the same config
+    pub fn save_config(&self, config: TreeConfig) -> Result<(), StoreError> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join(CONFIG_FILE), bincode::serialize(&config)?)?;
+
+        Ok(())
+    }
+
+    /// delete every cell in the store, along with its directory
+    pub fn clear(&self) -> Result<(), StoreError> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{fp::{Aabb, Vec3F}, spectrum::Spectrum, tree::Star};
+
+    #[test]
+    fn cell_format() {
+        let dir = std::env::temp_dir().join(format!("universe-engine-cell-format-{}", std::process::id()));
+        let store = CellStore::new(&dir);
+        let cell = Cell::<Star>::new(Aabb::new(Vec3F::ZERO, Vec3F::ONE * 64.0), Spectrum::ONE);
+        let id = cell.sector().id();
+
+        store.save(&cell).unwrap();
+        assert!(store.load::<Star>(id).unwrap().is_some());
+
+        // cells stored without a header, or with another format, are as good as missing
+        let write = |bytes: Vec<u8>| {
+            let mut writer = GzEncoder::new(fs::File::create(store.path(id)).unwrap(), Default::default());
+            writer.write_all(&bytes).unwrap();
+            writer.finish().unwrap();
+        };
+        write(bincode::serialize(&cell).unwrap());
+        assert!(store.load::<Star>(id).unwrap().is_none());
+        write([bincode::serialize(&CellHeader { format: CELL_FORMAT + 1, ..CellHeader::CURRENT }).unwrap(), bincode::serialize(&cell).unwrap()].concat());
+        assert!(store.load::<Star>(id).unwrap().is_none());
+        // or built from catalogues with another length of parsec
+        write([bincode::serialize(&CellHeader { parsec: 3.086e16, ..CellHeader::CURRENT }).unwrap(), bincode::serialize(&cell).unwrap()].concat());
+        assert!(store.load::<Star>(id).unwrap().is_none());
+        write(vec![]);
+        assert!(store.load::<Star>(id).unwrap().is_none());
+
+        store.clear().unwrap();
+        assert!(!dir.exists());
+    }
+}